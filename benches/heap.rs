@@ -0,0 +1,136 @@
+use YAIN::interaction;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use crate::interaction::{Agent, InteractionNet, Port, FREE};
+
+// Operations the workload needs from a net
+trait Net {
+    fn gen_id(&mut self) -> u64;
+    fn new_agent(&mut self, id: u64, atype: u64, arity: u64);
+    fn drop_agent(&mut self, id: u64);
+    fn bind_agents(&mut self, port0: Port, port1: Port);
+    fn link(&mut self, endpoint: Port, port: Port);
+}
+
+impl Net for InteractionNet {
+    fn gen_id(&mut self) -> u64 { InteractionNet::gen_id(self) }
+    fn new_agent(&mut self, id: u64, atype: u64, arity: u64) { InteractionNet::new_agent(self, id, atype, arity) }
+    fn drop_agent(&mut self, id: u64) { InteractionNet::drop_agent(self, id) }
+    fn bind_agents(&mut self, port0: Port, port1: Port) { InteractionNet::bind_agents(self, port0, port1) }
+    fn link(&mut self, endpoint: Port, port: Port) { InteractionNet::link(self, endpoint, port) }
+}
+
+// Heap as it was before the arena, every update clones the agent and inserts it again
+struct Baseline {
+    cnt: u64,
+    heap: HashMap<u64, Agent>,
+}
+
+impl Baseline {
+
+    fn new() -> Self {
+        Baseline { cnt: 1, heap: HashMap::new() }
+    }
+
+    fn port(&self, port: Port) -> Port {
+        self.heap.get(&port.0).unwrap().ports[port.1 as usize]
+    }
+
+    fn set_port(&mut self, port: Port, endpoint: Port) {
+        let mut copy = self.heap.get(&port.0).unwrap().clone();
+        copy.ports[port.1 as usize] = endpoint;
+        self.heap.insert(copy.id, copy);
+    }
+
+    fn unbind_agents(&mut self, port: Port) {
+        let other = self.port(port);
+        self.set_port(port, FREE);
+        if other != FREE && self.port(other) == port {
+            self.set_port(other, FREE);
+        }
+    }
+}
+
+impl Net for Baseline {
+
+    fn gen_id(&mut self) -> u64 {
+        self.cnt += 1;
+        self.cnt
+    }
+
+    fn new_agent(&mut self, id: u64, atype: u64, arity: u64) {
+        self.heap.insert(id, Agent { id, atype, ports: vec![FREE; arity as usize + 1], value: 0 });
+    }
+
+    fn drop_agent(&mut self, id: u64) {
+        let copy = self.heap.get(&id).unwrap().clone();
+        for index in 0..copy.ports.len() {
+            self.unbind_agents((id, index as u64));
+        }
+        self.heap.remove(&id);
+    }
+
+    fn bind_agents(&mut self, port0: Port, port1: Port) {
+        if port0.0 == 0 || port1.0 == 0 {
+            return;
+        }
+        self.set_port(port0, port1);
+        self.set_port(port1, port0);
+    }
+
+    fn link(&mut self, endpoint: Port, port: Port) {
+        let other = self.port(port);
+        self.unbind_agents(port);
+        self.bind_agents(endpoint, other);
+    }
+}
+
+// Chain of agents whose inner agents get replaced over and over like in a long reduction
+fn workload<N: Net>(net: &mut N, size: usize, rounds: usize) {
+    // Build chain, auxiliary port 1 is connected to principal port of next agent
+    let mut ids = vec![];
+    for _ in 0..size {
+        let id = net.gen_id();
+        net.new_agent(id, 0, 2);
+        ids.push(id);
+    }
+    for pair in ids.windows(2) {
+        net.bind_agents((pair[0], 1), (pair[1], 0));
+    }
+    for _ in 0..rounds {
+        for id in ids[1..size - 1].iter_mut() {
+            // Fresh agent takes over all wires of old one
+            let old = *id;
+            let new = net.gen_id();
+            net.new_agent(new, 0, 2);
+            for port in 0..3 {
+                net.link((new, port), (old, port));
+            }
+            net.drop_agent(old);
+            *id = new;
+        }
+    }
+    black_box(&ids);
+}
+
+// Best of several runs
+fn measure<F: FnMut()>(mut f: F) -> Duration {
+    (0..5).map(|_| {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    println!("{:>8} {:>8} {:>14} {:>14} {:>8}", "agents", "rounds", "hashmap", "arena", "speedup");
+    for (size, rounds) in [(100, 1000), (1000, 100), (10000, 10)] {
+        let baseline = measure(|| workload(&mut Baseline::new(), size, rounds));
+        let arena = measure(|| workload(&mut InteractionNet::new(), size, rounds));
+        println!("{:>8} {:>8} {:>14?} {:>14?} {:>7.2}x",
+                 size, rounds, baseline, arena,
+                 baseline.as_secs_f64() / arena.as_secs_f64());
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::interaction::vm::{Instruction, OPERATORS, VM};
+use crate::interaction::verify::{verify, VerifyError, RULE_DEPTH};
+
+// Assembly of rules and initial code, one instruction per line:
+//
+//   ; Comments run until the end of the line
+//   ADD # S:
+//       POP 0
+//       CONST 3
+//       OPERATE +
+//   init:
+//       GEN
+//
+// A label `A # B:` starts the rule for the active pair of types A and B, which are names of the
+// type mapping or plain numbers, `init:` starts the initial code. Mnemonics are the names of the
+// instructions, CONST, PUSH and POP take a number and OPERATE takes one of + - * < ==
+
+const INIT: &str = "init";
+
+// Why a text can not be assembled, lines are counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmError {
+    // Mnemonic does not name an instruction
+    Mnemonic { line: usize, name: String },
+    // Operand is missing, superfluous or not a number or operator
+    Operand { line: usize, text: String },
+    // Agent type of label is neither a number nor in the type mapping
+    Type { line: usize, name: String },
+    // Label is neither a rule nor init
+    Label { line: usize, text: String },
+    // Instruction before the first label
+    Unlabeled { line: usize },
+    // Section appears twice
+    Duplicate { line: usize, text: String },
+    // Code of section would break the vm
+    Verify { line: usize, error: VerifyError },
+}
+
+impl fmt::Display for AsmError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Mnemonic { line, name } => write!(f, "line {}: unknown instruction `{}`", line, name),
+            AsmError::Operand { line, text } => write!(f, "line {}: bad operand in `{}`", line, text),
+            AsmError::Type { line, name } => write!(f, "line {}: unknown agent type `{}`", line, name),
+            AsmError::Label { line, text } => write!(f, "line {}: expected `A # B:` or `init:`, found `{}`", line, text),
+            AsmError::Unlabeled { line } => write!(f, "line {}: instruction outside of a rule or init", line),
+            AsmError::Duplicate { line, text } => write!(f, "line {}: `{}` appears twice", line, text),
+            AsmError::Verify { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Rules and initial code read from assembly
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assembly {
+    pub rules: Vec<((u64, u64), Vec<Instruction>)>,
+    pub code: Option<Vec<Instruction>>, // None if there is no init section
+}
+
+impl Assembly {
+
+    // Add rules to vm, replacing rules for the same pairs, and replace initial code if there is one.
+    // Nothing is changed if some code does not pass the verifier
+    pub fn apply(&self, vm: &mut VM) -> Result<(), VerifyError> {
+        for (_, code) in &self.rules {
+            verify(code, RULE_DEPTH)?;
+        }
+        if let Some(code) = &self.code {
+            verify(code, 0)?;
+        }
+        for (atypes, code) in &self.rules {
+            vm.new_rewrite(*atypes, code.clone())?;
+        }
+        if let Some(code) = &self.code {
+            vm.code = code.clone();
+            vm.pc = 0;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Instruction {
+
+    // Mnemonic with operand, e.g. CONST 3
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::CONST(c) => write!(f, "CONST {}", c),
+            Instruction::PUSH(addr) => write!(f, "PUSH {}", addr),
+            Instruction::POP(addr) => write!(f, "POP {}", addr),
+            Instruction::OPERATE(operator) => write!(f, "OPERATE {}", operator.symbol()),
+            // Debug output of the others is their name
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+// Listing of code, one indented instruction per line
+pub fn disassemble_code(code: &[Instruction]) -> String {
+    code.iter().map(|instruction| format!("    {}\n", instruction)).collect()
+}
+
+// Listing of all rules and the initial code of vm, types are named after the type mapping
+pub fn disassemble(vm: &VM, type_mapping: &HashMap<String, u64>) -> String {
+    let names: HashMap<u64, &String> = type_mapping.iter().map(|(name, atype)| (*atype, name)).collect();
+    let name = |atype: u64| names.get(&atype).map(|name| name.to_string()).unwrap_or_else(|| atype.to_string());
+    let mut text = String::new();
+    for ((a, b), code) in vm.rules.iter() {
+        text.push_str(&format!("{} # {}:\n", name(a), name(b)));
+        text.push_str(&disassemble_code(code));
+    }
+    text.push_str(&format!("{}:\n", INIT));
+    text.push_str(&disassemble_code(&vm.code));
+    text
+}
+
+// Instruction of one line without comment
+pub fn instruction(line: usize, text: &str) -> Result<Instruction, AsmError> {
+    let mut words = text.split_whitespace();
+    let mnemonic = words.next().unwrap_or_default();
+    let operand = words.next();
+    if words.next().is_some() {
+        return Err(AsmError::Operand { line, text: text.to_string() });
+    }
+    let bad = || AsmError::Operand { line, text: text.to_string() };
+    let number = || operand.and_then(|operand| operand.parse::<u64>().ok()).ok_or_else(bad);
+    let instruction = match mnemonic {
+        "CONST" => return Ok(Instruction::CONST(number()?)),
+        "PUSH" => return Ok(Instruction::PUSH(number()?)),
+        "POP" => return Ok(Instruction::POP(number()?)),
+        "OPERATE" => {
+            let operator = OPERATORS.into_iter().find(|o| Some(o.symbol()) == operand).ok_or_else(bad)?;
+            return Ok(Instruction::OPERATE(operator));
+        }
+        "NOP" => Instruction::NOP,
+        "GEN" => Instruction::GEN,
+        "DUP" => Instruction::DUP,
+        "NEW_AGENT" => Instruction::NEW_AGENT,
+        "DROP_AGENT" => Instruction::DROP_AGENT,
+        "BIND" => Instruction::BIND,
+        "UNBIND" => Instruction::UNBIND,
+        "PORT" => Instruction::PORT,
+        "LINK" => Instruction::LINK,
+        "LOAD" => Instruction::LOAD,
+        "STORE" => Instruction::STORE,
+        name => return Err(AsmError::Mnemonic { line, name: name.to_string() }),
+    };
+    // Remaining instructions take no operand
+    match operand {
+        Some(_) => Err(bad()),
+        None => Ok(instruction),
+    }
+}
+
+// Read rules and initial code, names of agent types are looked up in the type mapping
+pub fn assemble(text: &str, type_mapping: &HashMap<String, u64>) -> Result<Assembly, AsmError> {
+    let mut assembly = Assembly::default();
+    // Rule of every label, None for init, in order of appearance with lines of label and instructions
+    let mut sections = vec![];
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.split(';').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(label) = text.strip_suffix(':') {
+            let label = label.trim();
+            let section = if label == INIT {
+                None
+            } else {
+                let (a, b) = label.split_once('#')
+                    .ok_or_else(|| AsmError::Label { line, text: text.to_string() })?;
+                let atype = |name: &str| {
+                    let name = name.trim();
+                    name.parse::<u64>().ok()
+                        .or_else(|| type_mapping.get(name).copied())
+                        .ok_or_else(|| AsmError::Type { line, name: name.to_string() })
+                };
+                Some((atype(a)?, atype(b)?))
+            };
+            if sections.iter().any(|(other, _)| *other == section) {
+                return Err(AsmError::Duplicate { line, text: text.to_string() });
+            }
+            sections.push((section, vec![line]));
+            match section {
+                Some(atypes) => assembly.rules.push((atypes, vec![])),
+                None => assembly.code = Some(vec![]),
+            }
+            continue;
+        }
+        let instruction = instruction(line, text)?;
+        // Instructions belong to the last label
+        match sections.last_mut() {
+            None => return Err(AsmError::Unlabeled { line }),
+            Some((None, lines)) => {
+                assembly.code.as_mut().unwrap().push(instruction);
+                lines.push(line);
+            }
+            Some((Some(_), lines)) => {
+                assembly.rules.last_mut().unwrap().1.push(instruction);
+                lines.push(line);
+            }
+        }
+    }
+
+    // Hand-written code must not break the vm it gets applied to
+    for (section, lines) in &sections {
+        let (code, depth) = match section {
+            Some(atypes) => (&assembly.rules.iter().find(|(other, _)| other == atypes).unwrap().1, RULE_DEPTH),
+            None => (assembly.code.as_ref().unwrap(), 0),
+        };
+        verify(code, depth).map_err(|error| {
+            let line = match error {
+                VerifyError::Underflow { pc, .. } | VerifyError::Address { pc, .. } => lines[pc + 1],
+                VerifyError::Unbalanced { .. } => *lines.last().unwrap(),
+            };
+            AsmError::Verify { line, error }
+        })?;
+    }
+    Ok(assembly)
+}
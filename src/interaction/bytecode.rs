@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+use crate::interaction::vm::{Fault, Instruction, Operator, VM};
+use crate::interaction::verify::{verify, VerifyError};
+
+// Compiled programs stored as .yainc files, all numbers are little endian:
+//
+//   header     magic "YAINC", version u16
+//   types      count u32, then (type u64, name) for every agent type
+//   rules      count u32, then (type u64, type u64, code) for every rule
+//   code       initial code building the net
+//   interface  count u32, then (name, agent id u64) for every boundary agent
+//
+// Names are a length u32 followed by utf-8 bytes, code is a length u32 followed by instructions,
+// each an opcode u8 with an operand u64 for CONST, PUSH and POP or an operator u8 for OPERATE.
+// Initial code creates the same agent ids whenever it runs on an empty net, so boundary agents are
+// stored with their ids
+
+pub const MAGIC: &[u8; 5] = b"YAINC";
+pub const VERSION: u16 = 1;
+
+// Why a file can not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // File does not start with the magic bytes
+    NotBytecode,
+    // File was written by another version of the format
+    Version(u16),
+    // File ends in the middle of a section
+    Truncated,
+    // Byte at offset does not stand for an instruction or operator
+    Opcode { offset: usize, opcode: u8 },
+    // Name at offset is not valid utf-8
+    Name { offset: usize },
+    // Bytes left after the last section
+    Trailing { offset: usize },
+    // Code would break the vm, rule is None for the initial code
+    Verify { rule: Option<(u64, u64)>, error: VerifyError },
+    // Rule for a type which is not in the types section
+    Type { rule: (u64, u64) },
+    // Initial code uses agents or ports it did not create
+    Run(Fault),
+    // Boundary agent is not created by the initial code
+    Interface { name: String, id: u64 },
+}
+
+impl fmt::Display for LoadError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "can not read bytecode: {}", e),
+            LoadError::NotBytecode => write!(f, "not a compiled program"),
+            LoadError::Version(version) => {
+                write!(f, "compiled program has version {}, expected {}", version, VERSION)
+            }
+            LoadError::Truncated => write!(f, "compiled program ends unexpectedly"),
+            LoadError::Opcode { offset, opcode } => write!(f, "unknown opcode {} at byte {}", opcode, offset),
+            LoadError::Name { offset } => write!(f, "name at byte {} is not valid utf-8", offset),
+            LoadError::Trailing { offset } => write!(f, "unexpected data after byte {}", offset),
+            LoadError::Verify { rule: Some((a, b)), error } => write!(f, "rule for {} # {}: {}", a, b, error),
+            LoadError::Verify { rule: None, error } => write!(f, "initial code: {}", error),
+            LoadError::Type { rule: (a, b) } => write!(f, "rule for {} # {} uses an unknown type", a, b),
+            LoadError::Run(fault) => write!(f, "initial code: {}", fault),
+            LoadError::Interface { name, id } => write!(f, "boundary agent {} {} does not exist", name, id),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+fn operator_code(operator: Operator) -> u8 {
+    match operator {
+        Operator::Add => 0,
+        Operator::Sub => 1,
+        Operator::Mul => 2,
+        Operator::Less => 3,
+        Operator::Equal => 4,
+    }
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    out.extend((name.len() as u32).to_le_bytes());
+    out.extend(name.as_bytes());
+}
+
+fn put_code(out: &mut Vec<u8>, code: &[Instruction]) {
+    out.extend((code.len() as u32).to_le_bytes());
+    for instruction in code {
+        let (opcode, operand) = match *instruction {
+            Instruction::NOP => (0, None),
+            Instruction::GEN => (1, None),
+            Instruction::CONST(c) => (2, Some(c)),
+            Instruction::DUP => (3, None),
+            Instruction::PUSH(addr) => (4, Some(addr)),
+            Instruction::POP(addr) => (5, Some(addr)),
+            Instruction::NEW_AGENT => (6, None),
+            Instruction::DROP_AGENT => (7, None),
+            Instruction::BIND => (8, None),
+            Instruction::UNBIND => (9, None),
+            Instruction::PORT => (10, None),
+            Instruction::LINK => (11, None),
+            Instruction::LOAD => (12, None),
+            Instruction::STORE => (13, None),
+            Instruction::OPERATE(operator) => {
+                out.extend([14, operator_code(operator)]);
+                continue;
+            }
+        };
+        out.push(opcode);
+        if let Some(operand) = operand {
+            out.extend(operand.to_le_bytes());
+        }
+    }
+}
+
+// Serialize rules, initial code and interface of a freshly compiled vm
+pub fn to_bytes(vm: &VM, type_mapping: &HashMap<String, u64>) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+
+    // Sorted, so the same program always gives the same file
+    let mut types: Vec<(&String, &u64)> = type_mapping.iter().collect();
+    types.sort_by_key(|(name, atype)| (**atype, *name));
+    out.extend((types.len() as u32).to_le_bytes());
+    for (name, atype) in types {
+        out.extend(atype.to_le_bytes());
+        put_name(&mut out, name);
+    }
+
+    out.extend((vm.rules.len() as u32).to_le_bytes());
+    for ((a, b), code) in vm.rules.iter() {
+        out.extend(a.to_le_bytes());
+        out.extend(b.to_le_bytes());
+        put_code(&mut out, code);
+    }
+
+    put_code(&mut out, &vm.code);
+
+    let mut interface: Vec<(&String, &u64)> = vm.interface.iter().collect();
+    interface.sort();
+    out.extend((interface.len() as u32).to_le_bytes());
+    for (name, id) in interface {
+        put_name(&mut out, name);
+        out.extend(id.to_le_bytes());
+    }
+    out
+}
+
+// Write compiled program, e.g. to a .yainc file
+pub fn write(vm: &VM, type_mapping: &HashMap<String, u64>, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&to_bytes(vm, type_mapping))
+}
+
+// Reads sections of a file front to back
+struct Input<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Input<'a> {
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.offset.checked_add(n).filter(|end| *end <= self.bytes.len()).ok_or(LoadError::Truncated)?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()? as usize;
+        let offset = self.offset;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| LoadError::Name { offset })
+    }
+
+    fn code(&mut self) -> Result<Vec<Instruction>, LoadError> {
+        let len = self.u32()? as usize;
+        // Every instruction takes at least one byte, a bogus length must not allocate
+        let mut code = Vec::with_capacity(len.min(self.bytes.len() - self.offset));
+        for _ in 0..len {
+            let offset = self.offset;
+            let instruction = match self.u8()? {
+                0 => Instruction::NOP,
+                1 => Instruction::GEN,
+                2 => Instruction::CONST(self.u64()?),
+                3 => Instruction::DUP,
+                4 => Instruction::PUSH(self.u64()?),
+                5 => Instruction::POP(self.u64()?),
+                6 => Instruction::NEW_AGENT,
+                7 => Instruction::DROP_AGENT,
+                8 => Instruction::BIND,
+                9 => Instruction::UNBIND,
+                10 => Instruction::PORT,
+                11 => Instruction::LINK,
+                12 => Instruction::LOAD,
+                13 => Instruction::STORE,
+                14 => {
+                    let offset = self.offset;
+                    Instruction::OPERATE(match self.u8()? {
+                        0 => Operator::Add,
+                        1 => Operator::Sub,
+                        2 => Operator::Mul,
+                        3 => Operator::Less,
+                        4 => Operator::Equal,
+                        opcode => return Err(LoadError::Opcode { offset, opcode }),
+                    })
+                }
+                opcode => return Err(LoadError::Opcode { offset, opcode }),
+            };
+            code.push(instruction);
+        }
+        Ok(code)
+    }
+}
+
+// Load compiled program into a new vm and build its initial net, returns vm and type mapping
+pub fn from_bytes(bytes: &[u8]) -> Result<(VM, HashMap<String, u64>), LoadError> {
+    let mut input = Input { bytes, offset: 0 };
+    if input.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(LoadError::NotBytecode);
+    }
+    let version = input.u16()?;
+    if version != VERSION {
+        return Err(LoadError::Version(version));
+    }
+
+    let mut type_mapping = HashMap::new();
+    for _ in 0..input.u32()? {
+        let atype = input.u64()?;
+        type_mapping.insert(input.name()?, atype);
+    }
+
+    let types: HashSet<u64> = type_mapping.values().copied().collect();
+    let mut vm = VM::new();
+    for _ in 0..input.u32()? {
+        let atypes = (input.u64()?, input.u64()?);
+        if !types.contains(&atypes.0) || !types.contains(&atypes.1) {
+            return Err(LoadError::Type { rule: atypes });
+        }
+        vm.new_rewrite(atypes, input.code()?).map_err(|error| LoadError::Verify { rule: Some(atypes), error })?;
+    }
+
+    vm.code = input.code()?;
+    verify(&vm.code, 0).map_err(|error| LoadError::Verify { rule: None, error })?;
+
+    let mut interface = HashMap::new();
+    for _ in 0..input.u32()? {
+        let name = input.name()?;
+        interface.insert(name, input.u64()?);
+    }
+
+    if input.offset != bytes.len() {
+        return Err(LoadError::Trailing { offset: input.offset });
+    }
+
+    // Run initial code to create connections, files may have been written by anyone
+    vm.run_checked().map_err(LoadError::Run)?;
+    if let Some((name, id)) = interface.iter().find(|(_, id)| !vm.interaction_net.heap.contains(**id)) {
+        return Err(LoadError::Interface { name: name.clone(), id: *id });
+    }
+    vm.interface = interface;
+    Ok((vm, type_mapping))
+}
+
+// Load compiled program, e.g. from a .yainc file
+pub fn load(input: &mut impl Read) -> Result<(VM, HashMap<String, u64>), LoadError> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    from_bytes(&bytes)
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use crate::interaction::*;
+use crate::interaction::vm::StuckPair;
+
+// Agent types of Lafont's interaction combinators
+pub const CON: u64 = 0; // Constructor γ
+pub const DUP: u64 = 1; // Duplicator δ
+pub const ERA: u64 = 2; // Eraser ε
+pub const VAR: u64 = 3; // Boundary agent of the net, its principal port is never connected
+pub const REF: u64 = 4; // REF + n stands for definition n of the book and gets expanded when it interacts
+
+// Nets made of γ, δ and ε only are reduced with the six rules of the combinators. Compiled programs
+// also hold references into a book of definitions, expanding one is a seventh rewrite. A finite net of
+// combinators can not unfold recursive rules ahead of time, and Lafont's way around it, copying the
+// encoding of all rules with δ, breaks down as soon as the copied nets contain δ themselves, which the
+// encoded agents do. References keep the book finite and are only expanded when they meet an agent,
+// so the reduction of compiled programs is not limited to the six rules of the combinators
+
+// Number of auxiliary ports of every agent type
+pub fn arity(atype: u64) -> u64 {
+    match atype {
+        CON | DUP => 2,
+        VAR => 1,
+        _ => 0,
+    }
+}
+
+// Net with a single free port, node n has id n + 1 and (0, 0) stands for the free port
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    pub nodes: Vec<u64>,          // Type of every node
+    pub wires: Vec<(Port, Port)>, // Connections between ports of nodes
+}
+
+impl Template {
+
+    pub fn new() -> Self {
+        Template {
+            nodes: Vec::new(),
+            wires: Vec::new(),
+        }
+    }
+
+    // Add node and return its id
+    pub fn node(&mut self, atype: u64) -> u64 {
+        self.nodes.push(atype);
+        self.nodes.len() as u64
+    }
+
+    // Connect two ports
+    pub fn connect(&mut self, port0: Port, port1: Port) {
+        self.wires.push((port0, port1));
+    }
+}
+
+// Interaction net reduced with the six rules of the combinators and the expansion of references
+#[derive(Debug, Clone)]
+pub struct Combinators {
+    pub interaction_net: InteractionNet,
+    pub book: Vec<Option<Template>>,     // Definitions of REF agents, None if there is nothing to expand to
+    pub interface: HashMap<String, u64>, // Boundary agent of every free variable of the initial net
+}
+
+impl Default for Combinators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Combinators {
+
+    pub fn new() -> Self {
+        Combinators {
+            interaction_net: InteractionNet::new(),
+            book: Vec::new(),
+            interface: HashMap::new(),
+        }
+    }
+
+    // Add definition and return type of agents referring to it
+    pub fn define(&mut self, template: Option<Template>) -> u64 {
+        self.book.push(template);
+        REF + self.book.len() as u64 - 1
+    }
+
+    // Create agent of given type and return its id
+    pub fn new_node(&mut self, atype: u64) -> u64 {
+        let id = self.interaction_net.gen_id();
+        self.interaction_net.new_agent(id, atype, arity(atype));
+        id
+    }
+
+    // Create nodes of template and connect its free port to root, returns ids of the nodes
+    pub fn instantiate(&mut self, template: &Template, root: Port) -> Vec<u64> {
+        let ids: Vec<u64> = template.nodes.iter().map(|atype| self.new_node(*atype)).collect();
+        let resolve = |port: Port| if port == FREE { root } else { (ids[port.0 as usize - 1], port.1) };
+        for (port0, port1) in &template.wires {
+            self.interaction_net.bind_agents(resolve(*port0), resolve(*port1));
+        }
+        ids
+    }
+
+    // Connect ports pairwise and remove dying agents, a port of a dying agent stands for the wire it is connected to
+    fn rewire(&mut self, dying: &[u64], links: &[(Port, Port)]) {
+        let net = &mut self.interaction_net;
+        let inside = |port: Port| dying.contains(&port.0);
+        // New connection of every port
+        let mut partner = HashMap::new();
+        for (port0, port1) in links {
+            partner.insert(*port0, *port1);
+            partner.insert(*port1, *port0);
+        }
+        let mut visited = HashSet::new();
+        // Port a wire ends at after passing through dying agents, None if it is a loop
+        let follow = |net: &InteractionNet, start: Port, visited: &mut HashSet<Port>| -> Option<Port> {
+            let mut port = start;
+            while inside(port) {
+                let next = net.port(port);
+                if next == FREE || !inside(next) {
+                    return Some(next);
+                }
+                // Wire continues at new connection of the port it reached
+                if !visited.insert(next) {
+                    return None;
+                }
+                port = partner[&next];
+                visited.insert(port);
+            }
+            Some(port)
+        };
+        for (port0, port1) in links {
+            if !visited.insert(*port0) || !visited.insert(*port1) {
+                continue;
+            }
+            let end0 = follow(net, *port0, &mut visited);
+            let end1 = follow(net, *port1, &mut visited);
+            match (end0, end1) {
+                (Some(FREE), Some(FREE)) | (None, _) | (_, None) => {}
+                // Wire ends nowhere, remaining end stays unconnected
+                (Some(FREE), Some(end)) | (Some(end), Some(FREE)) => net.unbind_agents(end),
+                (Some(end0), Some(end1)) => net.bind_agents(end0, end1),
+            }
+        }
+        for id in dying {
+            net.drop_agent(*id);
+        }
+    }
+
+    // Rewrite active pair, returns false if one agent has no definition to expand to
+    fn interact(&mut self, pair: (u64, u64)) -> bool {
+        let (a, b) = pair;
+        let atypes = (self.interaction_net.atype(a), self.interaction_net.atype(b));
+        match atypes {
+            // Annihilation, auxiliary ports of constructors are connected crosswise
+            (CON, CON) => self.rewire(&[a, b], &[((a, 1), (b, 2)), ((a, 2), (b, 1))]),
+            (DUP, DUP) => self.rewire(&[a, b], &[((a, 1), (b, 1)), ((a, 2), (b, 2))]),
+            (ERA, ERA) => self.rewire(&[a, b], &[]),
+            // Erasure, references are erased without expanding them
+            (ERA, _) | (_, ERA) => {
+                let (other, eraser) = if atypes.0 == ERA { (b, a) } else { (a, b) };
+                let mut links = vec![];
+                for index in 1..=self.interaction_net.arity(other) {
+                    let era = self.new_node(ERA);
+                    links.push(((other, index), (era, 0)));
+                }
+                self.rewire(&[eraser, other], &links);
+            }
+            // Expansion, a reference with a definition is preferred over one without
+            (t0, t1) if t0 >= REF || t1 >= REF => {
+                let defined = |t: u64| t >= REF && self.book[(t - REF) as usize].is_some();
+                let (reference, atype) = if defined(t0) {
+                    (a, t0)
+                } else if defined(t1) {
+                    (b, t1)
+                } else {
+                    return false;
+                };
+                let template = self.book[(atype - REF) as usize].clone().unwrap();
+                // Free port of template takes over the wire of the reference
+                let root = self.interaction_net.port((reference, 0));
+                self.instantiate(&template, root);
+                self.interaction_net.drop_agent(reference);
+            }
+            // Commutation, every agent gets copied to the auxiliary ports of the other one
+            _ => {
+                let (con, dup) = if atypes.0 == CON { (a, b) } else { (b, a) };
+                let dups = [self.new_node(DUP), self.new_node(DUP)];
+                let cons = [self.new_node(CON), self.new_node(CON)];
+                let mut links = vec![];
+                for i in 0..2 {
+                    links.push(((con, i as u64 + 1), (dups[i], 0)));
+                    links.push(((dup, i as u64 + 1), (cons[i], 0)));
+                    for (j, copy) in cons.iter().enumerate() {
+                        links.push(((dups[i], j as u64 + 1), (*copy, i as u64 + 1)));
+                    }
+                }
+                self.rewire(&[con, dup], &links);
+            }
+        }
+        true
+    }
+
+    // Reduce net to normal form, returns active pairs of references with nothing to expand to
+    pub fn reduce(&mut self) -> Vec<StuckPair> {
+        let mut stuck = vec![];
+        while let Some((pair, _)) = self.interaction_net.pop_pair() {
+            if !self.interact(pair) {
+                let atypes = (self.interaction_net.atype(pair.0), self.interaction_net.atype(pair.1));
+                stuck.push(StuckPair { pair, atypes, origin: None });
+            }
+        }
+        stuck
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use crate::interaction::{FREE, Port};
+use crate::interaction::vm::{Instruction, ReduceError, StuckPair, StuckPolicy, VM};
+
+// Reduces a vm one instruction or interaction at a time, driven by commands like
+//
+//   break ADD # S      stop when a rule for ADD and S starts
+//   continue           run until then
+//   step               execute one instruction
+//   stack              show what it did
+//
+// Interactions are started and ended by the same VM methods VM::reduce uses, so reduction can be
+// finished by the vm afterwards
+
+const HELP: &str = "\
+step, s            execute one instruction or start the next interaction
+next, n            finish the current or the next interaction
+continue, c        run until a breakpoint is hit or the net is normal
+break A # B        stop when a rule for active pairs of A and B starts
+break new A        stop before an agent of type A is created
+delete N           remove breakpoint N
+breakpoints        list breakpoints
+where              show current interaction and next instruction
+pair               show current active pair
+stack              show stack, top last
+scratchpad         show scratchpad addresses written while debugging
+agent ID           show agent and what its ports are connected to
+quit, q            leave the debugger
+";
+
+// Where to stop, types are matched in either order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Breakpoint {
+    Rule((u64, u64)),
+    Create(u64),
+}
+
+// What a step did
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Started { pair: (u64, u64), atypes: (u64, u64) },
+    Executed { offset: usize, instruction: Instruction },
+    Finished { atypes: (u64, u64) },
+    // Active pair there is no rule for, it stays in net for StuckPolicy::Error
+    NoRule(StuckPair),
+    // Breakpoint with index into Debugger::breakpoints is hit before the next instruction
+    Breakpoint(usize),
+    // No active pairs left, carries the pairs there was no rule for like VM::reduce
+    Normal(Vec<StuckPair>),
+    Error(ReduceError),
+}
+
+// Interaction between its first and last instruction
+#[derive(Debug, Clone, Copy)]
+struct Current {
+    pair: (u64, u64),
+    atypes: (u64, u64),
+    start: usize,
+    pc: usize,
+    end: usize,
+}
+
+pub struct Debugger<'a> {
+    pub vm: &'a mut VM,
+    pub breakpoints: Vec<Breakpoint>,
+    type_mapping: &'a HashMap<String, u64>,
+    current: Option<Current>,
+    stuck: Vec<StuckPair>, // Pairs without rule since the net was last normal
+    written: BTreeSet<u64>, // Scratchpad addresses stored to while debugging
+}
+
+impl<'a> Debugger<'a> {
+
+    pub fn new(vm: &'a mut VM, type_mapping: &'a HashMap<String, u64>) -> Self {
+        Debugger { vm, breakpoints: vec![], type_mapping, current: None, stuck: vec![], written: BTreeSet::new() }
+    }
+
+    fn name(&self, atype: u64) -> String {
+        self.type_mapping.iter().find(|(_, t)| **t == atype).map(|(name, _)| name.clone())
+            .unwrap_or_else(|| atype.to_string())
+    }
+
+    fn atype(&self, name: &str) -> Option<u64> {
+        let name = name.trim();
+        name.parse::<u64>().ok().or_else(|| self.type_mapping.get(name).copied())
+    }
+
+    // Start next interaction or execute next instruction of the current one
+    pub fn step(&mut self) -> Stop {
+        let vm = &mut *self.vm;
+        let Some(current) = &mut self.current else {
+            if vm.interaction_net.active_pairs.is_empty() {
+                // Put kept pairs back into net
+                if vm.policy == StuckPolicy::Keep {
+                    for sp in &self.stuck {
+                        vm.interaction_net.push_pair(sp.pair, sp.origin);
+                    }
+                }
+                return Stop::Normal(std::mem::take(&mut self.stuck));
+            }
+            let (pair, origin) = vm.interaction_net.pop_pair().unwrap();
+            let atypes = (vm.interaction_net.atype(pair.0), vm.interaction_net.atype(pair.1));
+            let Some((start, end)) = vm.rules.range(atypes) else {
+                let sp = StuckPair { pair, atypes, origin };
+                if vm.policy == StuckPolicy::Error {
+                    // Leave net as it was
+                    vm.interaction_net.push_pair(pair, origin);
+                    return Stop::Error(ReduceError::NoRule(sp));
+                }
+                self.stuck.push(sp.clone());
+                return Stop::NoRule(sp);
+            };
+            vm.begin_interaction(pair, atypes);
+            self.current = Some(Current { pair, atypes, start, pc: start, end });
+            return Stop::Started { pair, atypes };
+        };
+
+        let instruction = vm.rules.instruction(current.pc);
+        let offset = current.pc - current.start;
+        vm.execute(instruction);
+        if let Instruction::POP(addr) = instruction {
+            self.written.insert(addr);
+        }
+        current.pc += 1;
+        if current.pc < current.end {
+            return Stop::Executed { offset, instruction };
+        }
+        let atypes = current.atypes;
+        vm.end_interaction();
+        self.current = None;
+        Stop::Finished { atypes }
+    }
+
+    // Breakpoint at the current position, rules break right after they started
+    pub fn hit(&self) -> Option<usize> {
+        let current = self.current.as_ref()?;
+        let instruction = self.vm.rules.instruction(current.pc);
+        self.breakpoints.iter().position(|breakpoint| match *breakpoint {
+            Breakpoint::Rule((a, b)) => {
+                current.pc == current.start && (current.atypes == (a, b) || current.atypes == (b, a))
+            }
+            Breakpoint::Create(atype) => {
+                // Type is below the arity on stack
+                let stack = &self.vm.stack;
+                instruction == Instruction::NEW_AGENT && stack.len() >= 2 && stack[stack.len() - 2] == atype
+            }
+        })
+    }
+
+    // Step until done or a breakpoint is hit, the first step never breaks
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> Stop {
+        loop {
+            let stop = self.step();
+            if matches!(stop, Stop::Normal(_) | Stop::Error(_)) {
+                return stop;
+            }
+            if let Some(index) = self.hit() {
+                return Stop::Breakpoint(index);
+            }
+            if done(self) {
+                return stop;
+            }
+        }
+    }
+
+    // Run until current interaction, or the next one if there is none, is done. Named after the
+    // command, the debugger is no iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Stop {
+        self.run_until(|debugger| debugger.current.is_none())
+    }
+
+    // Run until a breakpoint is hit or the net is normal
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    // Complete current interaction ignoring breakpoints, so the vm can go on reducing
+    pub fn finish(&mut self) {
+        while self.current.is_some() {
+            self.step();
+        }
+    }
+
+    fn rule(&self, atypes: (u64, u64)) -> String {
+        format!("{} # {}", self.name(atypes.0), self.name(atypes.1))
+    }
+
+    fn breakpoint(&self, breakpoint: Breakpoint) -> String {
+        match breakpoint {
+            Breakpoint::Rule(atypes) => self.rule(atypes),
+            Breakpoint::Create(atype) => format!("new {}", self.name(atype)),
+        }
+    }
+
+    fn stop(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Started { .. } | Stop::Executed { .. } => self.position(),
+            Stop::Finished { atypes } => format!("finished {}\n{}", self.rule(atypes), self.position()),
+            Stop::NoRule(sp) => format!("no rule for {} on pair {} {}\n", self.rule(sp.atypes), sp.pair.0, sp.pair.1),
+            Stop::Breakpoint(index) => {
+                format!("breakpoint {}: {}\n{}", index, self.breakpoint(self.breakpoints[index]), self.position())
+            }
+            Stop::Normal(stuck) => {
+                let mut text = "net is normal\n".to_string();
+                for sp in stuck {
+                    text.push_str(&format!("no rule for {} on pair {} {}\n", self.rule(sp.atypes), sp.pair.0, sp.pair.1));
+                }
+                text
+            }
+            Stop::Error(ReduceError::NoRule(sp)) => {
+                format!("stopped, no rule for {} on pair {} {}\n", self.rule(sp.atypes), sp.pair.0, sp.pair.1)
+            }
+        }
+    }
+
+    // Current interaction with next instruction
+    pub fn position(&self) -> String {
+        match &self.current {
+            Some(current) => format!(
+                "{} on pair {} {}, next {}: {}\n",
+                self.rule(current.atypes), current.pair.0, current.pair.1,
+                current.pc - current.start, self.vm.rules.instruction(current.pc),
+            ),
+            None => format!("between interactions, {} active pair(s)\n", self.vm.interaction_net.active_pairs.len()),
+        }
+    }
+
+    fn endpoint(&self, port: Port) -> String {
+        if port == FREE {
+            return "free".to_string();
+        }
+        match self.vm.interaction_net.heap.get(port.0) {
+            Some(agent) => format!("{} {} port {}", agent.id, self.name(agent.atype), port.1),
+            None => format!("{} (dropped) port {}", port.0, port.1),
+        }
+    }
+
+    // Agent with the endpoint of every port
+    pub fn neighbourhood(&self, id: u64) -> String {
+        let Some(agent) = self.vm.interaction_net.heap.get(id) else {
+            return format!("no agent {}\n", id);
+        };
+        let mut text = format!("{} {}", agent.id, self.name(agent.atype));
+        if agent.value != 0 {
+            text.push_str(&format!(" = {}", agent.value));
+        }
+        text.push('\n');
+        for (index, port) in agent.ports.iter().enumerate() {
+            text.push_str(&format!("  port {} -> {}\n", index, self.endpoint(*port)));
+        }
+        text
+    }
+
+    // Execute one line, returns what to print or None to quit
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let text = match word {
+            "" => String::new(),
+            "step" | "s" => {
+                let stop = self.step();
+                self.stop(stop)
+            }
+            "next" | "n" => {
+                let stop = self.next();
+                self.stop(stop)
+            }
+            "continue" | "c" => {
+                let stop = self.cont();
+                self.stop(stop)
+            }
+            "break" | "b" => {
+                let breakpoint = match rest.strip_prefix("new ") {
+                    Some(name) => self.atype(name).map(Breakpoint::Create),
+                    None => rest.split_once('#')
+                        .and_then(|(a, b)| Some(Breakpoint::Rule((self.atype(a)?, self.atype(b)?)))),
+                };
+                match breakpoint {
+                    Some(breakpoint) => {
+                        self.breakpoints.push(breakpoint);
+                        format!("breakpoint {}: {}\n", self.breakpoints.len() - 1, self.breakpoint(breakpoint))
+                    }
+                    None => format!("expected `break A # B` or `break new A` with known types, found `{}`\n", line),
+                }
+            }
+            "delete" | "d" => match rest.parse::<usize>() {
+                Ok(index) if index < self.breakpoints.len() => {
+                    self.breakpoints.remove(index);
+                    String::new()
+                }
+                _ => format!("no breakpoint `{}`\n", rest),
+            },
+            "breakpoints" => (0..self.breakpoints.len())
+                .map(|index| format!("{}: {}\n", index, self.breakpoint(self.breakpoints[index])))
+                .collect(),
+            "where" | "w" => self.position(),
+            "pair" => match &self.current {
+                Some(current) => {
+                    let (a, b) = current.pair;
+                    format!("{}{}", self.neighbourhood(a), self.neighbourhood(b))
+                }
+                None => "no interaction running\n".to_string(),
+            },
+            "stack" => format!("{:?}\n", self.vm.stack),
+            "scratchpad" => self.written.iter()
+                .map(|addr| format!("{}: {}\n", addr, self.vm.scratchpad[*addr as usize]))
+                .collect(),
+            "agent" | "a" => match rest.parse::<u64>() {
+                Ok(id) => self.neighbourhood(id),
+                Err(_) => format!("expected agent id, found `{}`\n", rest),
+            },
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => return None,
+            _ => format!("unknown command `{}`, try help\n", word),
+        };
+        Some(text)
+    }
+
+    // Read commands line by line until quit or end of input, then finish the current interaction
+    pub fn repl(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            write!(output, "(yain) ")?;
+            output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            match self.command(&line) {
+                Some(text) => write!(output, "{}", text)?,
+                None => break,
+            }
+        }
+        self.finish();
+        Ok(())
+    }
+}
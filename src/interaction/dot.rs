@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+use crate::interaction::{InteractionNet, Port, FREE};
+use crate::interaction::vm::{Outcome, ReduceError, StuckPair, VM};
+
+// Nets as Graphviz DOT graphs, e.g. for `dot -Tsvg net.dot`
+//
+// Agents are boxes labelled with the name of their type and non-zero values. Every wire is drawn
+// once, its ends carry a dot at principal ports and the port index at auxiliary ports. Agents of
+// active pairs are filled and the wire between them is red
+
+fn end(port: Port) -> (&'static str, String) {
+    match port.1 {
+        0 => ("dot", String::new()),
+        index => ("none", index.to_string()),
+    }
+}
+
+// Net as DOT graph, title is shown below the graph if it is not empty
+pub fn to_dot(net: &InteractionNet, type_mapping: &HashMap<String, u64>, title: &str) -> String {
+    let names: HashMap<u64, &String> = type_mapping.iter().map(|(name, atype)| (*atype, name)).collect();
+    let name = |atype: u64| names.get(&atype).map(|name| name.to_string()).unwrap_or_else(|| atype.to_string());
+    let active: HashSet<u64> = net.active_pairs.iter().flat_map(|(a, b)| [*a, *b]).collect();
+
+    let mut text = String::from("digraph net {\n");
+    if !title.is_empty() {
+        text.push_str(&format!("  label=\"{}\";\n", title.replace('"', "\\\"")));
+    }
+    text.push_str("  node [shape=box, style=rounded];\n");
+    text.push_str("  edge [dir=both, arrowhead=none, arrowtail=none];\n");
+
+    // Sorted, so the same net always gives the same graph
+    let mut agents: Vec<_> = net.heap.values().collect();
+    agents.sort_by_key(|agent| agent.id);
+    for agent in &agents {
+        let mut label = name(agent.atype);
+        if agent.value != 0 {
+            label.push_str(&format!(" {}", agent.value));
+        }
+        let fill = if active.contains(&agent.id) { ", style=\"rounded,filled\", fillcolor=lightpink" } else { "" };
+        text.push_str(&format!("  a{} [label=\"{}\"{}];\n", agent.id, label.replace('"', "\\\""), fill));
+    }
+
+    for agent in &agents {
+        for (index, other) in agent.ports.iter().enumerate() {
+            let port = (agent.id, index as u64);
+            // Each wire is seen from both ends, dangling ones are left out
+            if *other == FREE || *other < port || !net.heap.contains(other.0) {
+                continue;
+            }
+            let (tail, taillabel) = end(port);
+            let (head, headlabel) = end(*other);
+            let pair = net.active_pairs.contains(&(port.0, other.0)) || net.active_pairs.contains(&(other.0, port.0));
+            let color = if pair && port.1 == 0 && other.1 == 0 { ", color=red, penwidth=2" } else { "" };
+            text.push_str(&format!(
+                "  a{} -> a{} [arrowtail={}, arrowhead={}, taillabel=\"{}\", headlabel=\"{}\"{}];\n",
+                port.0, other.0, tail, head, taillabel, headlabel, color,
+            ));
+        }
+    }
+    text.push_str("}\n");
+    text
+}
+
+// Reduce net of vm one interaction at a time and render it before the first and after every
+// interaction, at most limit interactions are run. Returns the frames together with the active pairs
+// there was no rule for once normal form is reached, before that they are still in the net
+pub fn frames(
+    vm: &mut VM,
+    type_mapping: &HashMap<String, u64>,
+    limit: Option<u64>,
+) -> Result<(Vec<String>, Vec<StuckPair>), ReduceError> {
+    let mut frames = vec![to_dot(&vm.interaction_net, type_mapping, "interaction 0")];
+    let mut stuck = vec![];
+    let mut interactions = 0;
+    while limit.is_none_or(|limit| interactions < limit) {
+        let interactions_before = vm.stats.interactions;
+        let outcome = vm.reduce_with_limit(1)?;
+        if vm.stats.interactions > interactions_before {
+            interactions += 1;
+            frames.push(to_dot(&vm.interaction_net, type_mapping, &format!("interaction {}", interactions)));
+        }
+        if let Outcome::Normal(pairs) = outcome {
+            stuck = pairs;
+            break;
+        }
+    }
+    Ok((frames, stuck))
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::collections::BTreeMap;
+use crate::interaction::Agent;
+
+// Contiguous agent storage indexed by id, ids of dropped agents get reused.
+// A sparse heap keeps agents in a map instead, for a few agents with large ids
+#[derive(Clone, Default)]
+pub struct Heap {
+    slots: Vec<Option<Agent>>,            // slots[id] holds agent with that id
+    sparse: Option<BTreeMap<u64, Agent>>, // Agents of a sparse heap, slots stay empty then
+    free: Vec<u64>,                       // Ids of dropped agents
+    len: usize,                           // Number of living agents
+}
+
+impl Heap {
+
+    pub fn new() -> Self {
+        Heap {
+            slots: Vec::new(),
+            sparse: None,
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn sparse() -> Self {
+        Heap { sparse: Some(BTreeMap::new()), ..Heap::new() }
+    }
+
+    // Number of living agents
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Take id of a dropped agent if there is one
+    pub fn recycle(&mut self) -> Option<u64> {
+        while let Some(id) = self.free.pop() {
+            // Id might have been taken by hand in the meantime
+            if !self.contains(id) {
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Agent> {
+        match &self.sparse {
+            None => self.slots.get(id as usize).and_then(|slot| slot.as_ref()),
+            Some(agents) => agents.get(&id),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Agent> {
+        match &mut self.sparse {
+            None => self.slots.get_mut(id as usize).and_then(|slot| slot.as_mut()),
+            Some(agents) => agents.get_mut(&id),
+        }
+    }
+
+    // Store agent in slot of its id, replaces agent with same id
+    pub fn insert(&mut self, agent: Agent) {
+        if let Some(agents) = &mut self.sparse {
+            if agents.insert(agent.id, agent).is_none() {
+                self.len += 1;
+            }
+            return;
+        }
+        let index = agent.id as usize;
+        // Grow arena up to id
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, None);
+        }
+        if self.slots[index].is_none() {
+            self.len += 1;
+        }
+        self.slots[index] = Some(agent);
+    }
+
+    // Remove agent and remember id for reuse
+    pub fn remove(&mut self, id: u64) -> Option<Agent> {
+        let agent = self.take(id);
+        if agent.is_some() {
+            self.free.push(id);
+        }
+        agent
+    }
+
+    // Remove agent without giving its id away, agent is expected to come back
+    pub fn take(&mut self, id: u64) -> Option<Agent> {
+        let agent = match &mut self.sparse {
+            None => self.slots.get_mut(id as usize).and_then(|slot| slot.take()),
+            Some(agents) => agents.remove(&id),
+        };
+        if agent.is_some() {
+            self.len -= 1;
+        }
+        agent
+    }
+
+    // Make id available for reuse
+    pub fn release(&mut self, id: u64) {
+        self.free.push(id);
+    }
+
+    // Ids waiting for reuse, the last one is taken first
+    pub fn free_ids(&self) -> &[u64] {
+        &self.free
+    }
+
+    // Living agents in order of their ids
+    pub fn values(&self) -> impl Iterator<Item = &Agent> {
+        self.slots.iter().filter_map(|slot| slot.as_ref()).chain(self.sparse.iter().flat_map(|agents| agents.values()))
+    }
+}
+
+impl fmt::Debug for Heap {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.values().map(|agent| (agent.id, agent))).finish()
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::interaction::{Agent, InteractionNet, Port, FREE};
+use crate::interaction::assembly::instruction;
+use crate::interaction::verify::{verify, VerifyError};
+use crate::interaction::vm::{Instruction, VM};
+
+// Nets and vms as JSON documents for other tools, e.g.
+//
+//   {
+//     "format": "yain-net",
+//     "version": 1,
+//     "types": {"ADD": 0, "S": 1},
+//     "last_id": 3,
+//     "free_ids": [],
+//     "agents": [
+//       {"id": 2, "type": "ADD", "value": 0, "ports": [[3, 0], null]},
+//       {"id": 3, "type": "S", "value": 0, "ports": [[2, 0], null]}
+//     ],
+//     "active_pairs": [[2, 3]]
+//   }
+//
+// Agent types are written as names of the type mapping, or as numbers if they have no name, and
+// both are read. Ports hold the [agent id, port index] they are connected to or null if free.
+// last_id is the id given out last and free_ids are ids of dropped agents waiting for reuse, so new
+// agents get the same ids as they would have got without the round trip. Every wire between two
+// principal ports is listed in active_pairs, pairs without rule a reduction removed from the active
+// pairs are written after them.
+//
+// A "yain-vm" document holds the same types and additionally
+//
+//   "rules"      [{"left": "ADD", "right": "S", "code": ["POP 0", "POP 1", ...]}, ...]
+//   "code"       initial code as assembly, "pc" is the next instruction of it
+//   "interface"  {"r": 5, ...} boundary agent of every free variable of the initial net
+//   "net"        net as above, without format, version and types
+//   "origins"    rule which created each active pair as [left, right], null for none or unknown
+//
+// Rules may only use types of the type mapping. Vms are meant to be written between interactions,
+// stack and scratchpad are not part of them
+
+pub const NET_FORMAT: &str = "yain-net";
+pub const VM_FORMAT: &str = "yain-vm";
+pub const VERSION: u64 = 1;
+
+// Why a document can not be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    // Text is no JSON or its values do not have the types of the document, message of serde_json
+    Syntax(String),
+    // Document is no net or vm of this version
+    Format { expected: &'static str },
+    Version(u64),
+    // Field at path does not fit the net it describes, e.g. net.agents[3].ports[1]
+    Schema { path: String },
+    // Code would break the vm, rule is None for the initial code
+    Verify { rule: Option<(u64, u64)>, error: VerifyError },
+}
+
+impl fmt::Display for JsonError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::Syntax(message) => write!(f, "malformed document: {}", message),
+            JsonError::Format { expected } => write!(f, "document is no {}", expected),
+            JsonError::Version(version) => write!(f, "document has version {}, expected {}", version, VERSION),
+            JsonError::Schema { path } => write!(f, "field `{}` is malformed", path),
+            JsonError::Verify { rule: Some((a, b)), error } => write!(f, "rule for {} # {}: {}", a, b, error),
+            JsonError::Verify { rule: None, error } => write!(f, "initial code: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(e: serde_json::Error) -> Self {
+        JsonError::Syntax(e.to_string())
+    }
+}
+
+// Agent type as name of the type mapping or number
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Type {
+    Name(String),
+    Number(u64),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AgentDocument {
+    id: u64,
+    #[serde(rename = "type")]
+    atype: Type,
+    value: u64,
+    ports: Vec<Option<Port>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NetDocument {
+    last_id: u64,
+    free_ids: Vec<u64>,
+    agents: Vec<AgentDocument>,
+    active_pairs: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RuleDocument {
+    left: Type,
+    right: Type,
+    code: Vec<String>,
+}
+
+// Fields every document starts with, read first to tell documents apart
+#[derive(Debug, Deserialize)]
+struct Header {
+    format: Option<String>,
+    version: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NetFile {
+    format: String,
+    version: u64,
+    types: BTreeMap<String, u64>,
+    #[serde(flatten)]
+    net: NetDocument,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VmFile {
+    format: String,
+    version: u64,
+    types: BTreeMap<String, u64>,
+    rules: Vec<RuleDocument>,
+    code: Vec<String>,
+    pc: usize,
+    interface: BTreeMap<String, u64>,
+    net: NetDocument,
+    origins: Vec<Option<(Type, Type)>>,
+}
+
+// Names of agent types, types without name are written as numbers
+struct Names(HashMap<u64, String>);
+
+impl Names {
+
+    fn new(type_mapping: &HashMap<String, u64>) -> Self {
+        Names(type_mapping.iter().map(|(name, atype)| (*atype, name.clone())).collect())
+    }
+
+    fn atype(&self, atype: u64) -> Type {
+        match self.0.get(&atype) {
+            Some(name) => Type::Name(name.clone()),
+            None => Type::Number(atype),
+        }
+    }
+}
+
+fn types(type_mapping: &HashMap<String, u64>) -> BTreeMap<String, u64> {
+    type_mapping.iter().map(|(name, atype)| (name.clone(), *atype)).collect()
+}
+
+// Instructions as assembly
+fn code(code: &[Instruction]) -> Vec<String> {
+    code.iter().map(|instruction| instruction.to_string()).collect()
+}
+
+// Wires between principal ports which are not active pairs of the net, the pairs a reduction collected
+fn collected(net: &InteractionNet, agents: &[&Agent]) -> Vec<(u64, u64)> {
+    let paired: HashSet<u64> = net.active_pairs.iter().flat_map(|(a, b)| [*a, *b]).collect();
+    agents.iter()
+        .filter(|agent| agent.ports[0].1 == 0 && agent.ports[0] != FREE && agent.id < agent.ports[0].0)
+        .filter(|agent| !paired.contains(&agent.id))
+        .map(|agent| (agent.id, agent.ports[0].0))
+        .collect()
+}
+
+// Agents sorted by id, so the same net always gives the same document
+fn sorted(net: &InteractionNet) -> Vec<&Agent> {
+    let mut agents: Vec<&Agent> = net.heap.values().collect();
+    agents.sort_by_key(|agent| agent.id);
+    agents
+}
+
+fn net_document(net: &InteractionNet, names: &Names) -> NetDocument {
+    let agents = sorted(net);
+    let active_pairs = net.active_pairs.iter().copied().chain(collected(net, &agents)).collect();
+    NetDocument {
+        // Agents created with ids of their own must not be handed out again after reading
+        last_id: agents.last().map_or(net.cnt, |agent| net.cnt.max(agent.id)),
+        free_ids: net.heap.free_ids().to_vec(),
+        agents: agents.into_iter().map(|agent| AgentDocument {
+            id: agent.id,
+            atype: names.atype(agent.atype),
+            value: agent.value,
+            ports: agent.ports.iter().map(|port| Some(*port).filter(|port| *port != FREE)).collect(),
+        }).collect(),
+        active_pairs,
+    }
+}
+
+// Net with its type mapping as JSON text
+pub fn net_to_json(net: &InteractionNet, type_mapping: &HashMap<String, u64>) -> String {
+    let file = NetFile {
+        format: NET_FORMAT.to_string(),
+        version: VERSION,
+        types: types(type_mapping),
+        net: net_document(net, &Names::new(type_mapping)),
+    };
+    format!("{}\n", serde_json::to_string_pretty(&file).unwrap())
+}
+
+// Vm with rules, initial code, interface and net as JSON text
+pub fn vm_to_json(vm: &VM, type_mapping: &HashMap<String, u64>) -> String {
+    let names = Names::new(type_mapping);
+    let file = VmFile {
+        format: VM_FORMAT.to_string(),
+        version: VERSION,
+        types: types(type_mapping),
+        rules: vm.rules.iter().map(|((a, b), rule)| RuleDocument {
+            left: names.atype(a),
+            right: names.atype(b),
+            code: code(rule),
+        }).collect(),
+        code: code(&vm.code),
+        pc: vm.pc,
+        interface: vm.interface.iter().map(|(name, id)| (name.clone(), *id)).collect(),
+        net: net_document(&vm.interaction_net, &names),
+        // Rules which created collected pairs are not known any more
+        origins: vm.interaction_net.origins.iter()
+            .map(|origin| origin.map(|(a, b)| (names.atype(a), names.atype(b))))
+            .chain(collected(&vm.interaction_net, &sorted(&vm.interaction_net)).iter().map(|_| None))
+            .collect(),
+    };
+    format!("{}\n", serde_json::to_string_pretty(&file).unwrap())
+}
+
+fn schema<T>(path: String) -> Result<T, JsonError> {
+    Err(JsonError::Schema { path })
+}
+
+// Check format and version before the document is read as a whole
+fn header(value: &Value, format: &'static str) -> Result<(), JsonError> {
+    let header = Header::deserialize(value).map_err(|_| JsonError::Format { expected: format })?;
+    if header.format.as_deref() != Some(format) {
+        return Err(JsonError::Format { expected: format });
+    }
+    match header.version {
+        Some(VERSION) => Ok(()),
+        Some(version) => Err(JsonError::Version(version)),
+        None => schema("version".to_string()),
+    }
+}
+
+// Turns documents into nets, errors carry the path of the field
+struct Reader {
+    type_mapping: HashMap<String, u64>,
+    known: HashSet<u64>, // Types of the type mapping
+}
+
+impl Reader {
+
+    fn new(types: BTreeMap<String, u64>) -> Self {
+        let type_mapping: HashMap<String, u64> = types.into_iter().collect();
+        let known = type_mapping.values().copied().collect();
+        Reader { type_mapping, known }
+    }
+
+    // Name of the type mapping or any number
+    fn atype(&self, atype: &Type, path: String) -> Result<u64, JsonError> {
+        match atype {
+            Type::Number(atype) => Ok(*atype),
+            Type::Name(name) => self.type_mapping.get(name).copied().ok_or(JsonError::Schema { path }),
+        }
+    }
+
+    // Type of the type mapping, by name or number
+    fn known(&self, atype: &Type, path: String) -> Result<u64, JsonError> {
+        match self.atype(atype, path.clone())? {
+            atype if self.known.contains(&atype) => Ok(atype),
+            _ => schema(path),
+        }
+    }
+
+    fn net(&self, document: NetDocument, parent: &str) -> Result<InteractionNet, JsonError> {
+        let mut net = InteractionNet::new();
+        net.cnt = document.last_id;
+
+        for (i, agent) in document.agents.iter().enumerate() {
+            let path = format!("{}agents[{}]", parent, i);
+            // Id 0 marks free ports, ids above last_id would be given out again
+            if agent.id == 0 || agent.id > document.last_id || net.heap.contains(agent.id) {
+                return schema(format!("{}.id", path));
+            }
+            // Every agent has a principal port
+            if agent.ports.is_empty() {
+                return schema(format!("{}.ports", path));
+            }
+            net.heap.insert(Agent {
+                id: agent.id,
+                atype: self.atype(&agent.atype, format!("{}.type", path))?,
+                ports: agent.ports.iter().map(|port| port.unwrap_or(FREE)).collect(),
+                value: agent.value,
+            });
+        }
+        // Wires must end at other ports of agents of the net which lead back
+        for (i, agent) in document.agents.iter().enumerate() {
+            for (j, endpoint) in agent.ports.iter().enumerate() {
+                let Some(endpoint) = *endpoint else { continue };
+                let back = net.heap.get(endpoint.0).and_then(|other| other.ports.get(endpoint.1 as usize));
+                if endpoint == (agent.id, j as u64) || back != Some(&(agent.id, j as u64)) {
+                    return schema(format!("{}agents[{}].ports[{}]", parent, i, j));
+                }
+            }
+        }
+
+        let mut free = HashSet::new();
+        for (i, id) in document.free_ids.iter().enumerate() {
+            if *id == 0 || *id > document.last_id || net.heap.contains(*id) || !free.insert(*id) {
+                return schema(format!("{}free_ids[{}]", parent, i));
+            }
+            net.heap.release(*id);
+        }
+
+        // Pairs are wired principal to principal and no agent takes part in two
+        let mut paired = HashSet::new();
+        for (i, (a, b)) in document.active_pairs.iter().enumerate() {
+            let wired = net.heap.get(*a).is_some_and(|agent| agent.ports[0] == (*b, 0));
+            if a == b || !wired || !paired.insert(*a) || !paired.insert(*b) {
+                return schema(format!("{}active_pairs[{}]", parent, i));
+            }
+            net.push_pair((*a, *b), None);
+        }
+        // Principal ports wired to each other are always an active pair
+        for (i, agent) in document.agents.iter().enumerate() {
+            if agent.ports[0].is_some_and(|(_, pid)| pid == 0) && !paired.contains(&agent.id) {
+                return schema(format!("{}agents[{}].ports[0]", parent, i));
+            }
+        }
+        Ok(net)
+    }
+
+    fn code(&self, code: &[String], path: &str) -> Result<Vec<Instruction>, JsonError> {
+        code.iter().enumerate()
+            .map(|(i, text)| {
+                let text = Some(text.trim()).filter(|text| !text.is_empty());
+                text.and_then(|text| instruction(0, text).ok())
+                    .ok_or(JsonError::Schema { path: format!("{}[{}]", path, i) })
+            })
+            .collect()
+    }
+}
+
+// Read net written by net_to_json, returns net and type mapping
+pub fn net_from_json(text: &str) -> Result<(InteractionNet, HashMap<String, u64>), JsonError> {
+    let value: Value = serde_json::from_str(text)?;
+    header(&value, NET_FORMAT)?;
+    let file = NetFile::deserialize(value)?;
+    let reader = Reader::new(file.types);
+    let net = reader.net(file.net, "")?;
+    Ok((net, reader.type_mapping))
+}
+
+// Read vm written by vm_to_json, code is verified like code of bytecode files
+pub fn vm_from_json(text: &str) -> Result<(VM, HashMap<String, u64>), JsonError> {
+    let value: Value = serde_json::from_str(text)?;
+    header(&value, VM_FORMAT)?;
+    let file = VmFile::deserialize(value)?;
+    let reader = Reader::new(file.types);
+    let mut vm = VM::new();
+
+    for (i, rule) in file.rules.iter().enumerate() {
+        let path = format!("rules[{}]", i);
+        let atypes = (
+            reader.known(&rule.left, format!("{}.left", path))?,
+            reader.known(&rule.right, format!("{}.right", path))?,
+        );
+        vm.new_rewrite(atypes, reader.code(&rule.code, &format!("{}.code", path))?)
+            .map_err(|error| JsonError::Verify { rule: Some(atypes), error })?;
+    }
+
+    vm.code = reader.code(&file.code, "code")?;
+    verify(&vm.code, 0).map_err(|error| JsonError::Verify { rule: None, error })?;
+    vm.pc = file.pc;
+    if vm.pc > vm.code.len() {
+        return schema("pc".to_string());
+    }
+
+    vm.interaction_net = reader.net(file.net, "net.")?;
+    // Boundary agents have to be part of the net
+    for (name, id) in &file.interface {
+        if !vm.interaction_net.heap.contains(*id) {
+            return schema(format!("interface.{}", name));
+        }
+    }
+    vm.interface = file.interface.into_iter().collect();
+
+    if file.origins.len() != vm.interaction_net.active_pairs.len() {
+        return schema("origins".to_string());
+    }
+    for (i, origin) in file.origins.iter().enumerate() {
+        vm.interaction_net.origins[i] = match origin {
+            Some((a, b)) => {
+                let path = format!("origins[{}]", i);
+                Some((reader.atype(a, format!("{}[0]", path))?, reader.atype(b, format!("{}[1]", path))?))
+            }
+            None => None,
+        };
+    }
+    Ok((vm, reader.type_mapping))
+}
@@ -1,19 +1,48 @@
 pub mod vm;
+pub mod heap;
+pub mod rules;
+pub mod parallel;
+pub mod combinators;
+pub mod bytecode;
+pub mod assembly;
+pub mod verify;
+pub mod stats;
+pub mod debug;
+pub mod dot;
+pub mod json;
 
-use std::collections::HashMap;
+use crate::interaction::heap::Heap;
+
+// Wire endpoint (agent id, port index), port 0 is the principal port
+pub type Port = (u64, u64);
+
+// Endpoint of a port that is not connected
+pub const FREE: Port = (0, 0);
+
+// Types of the rule which created an active pair, None for pairs of the initial net
+pub type Origin = Option<(u64, u64)>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Agent {
     pub id: u64,        /* NOTE: 0 is not a valid id!!! */
     pub atype: u64,
-    pub ports: Vec<u64>,
+    pub ports: Vec<Port>, // Endpoint every port is connected to, ports[0] is the principal port
+    pub value: u64,       // Payload of numbers, 0 for all other agents
 }
 
 #[derive(Debug, Clone)]
 pub struct InteractionNet {
     pub cnt: u64,
-    pub heap: HashMap<u64, Agent>,
+    pub heap: Heap,
     pub active_pairs: Vec<(u64, u64)>,
+    pub origins: Vec<Origin>,   // Rule which created active_pairs[i]
+    pub origin: Origin,         // Rule running right now, new pairs are tagged with it
+}
+
+impl Default for InteractionNet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InteractionNet {
@@ -21,104 +50,123 @@ impl InteractionNet {
     pub fn new() -> Self {
         InteractionNet {
             cnt: 1,
-            heap: HashMap::new(),
+            heap: Heap::new(),
             active_pairs: Vec::new(),
+            origins: Vec::new(),
+            origin: None,
         }
     }
 
+    // Add active pair together with the rule which created it
+    pub fn push_pair(&mut self, pair: (u64, u64), origin: Origin) {
+        self.active_pairs.push(pair);
+        self.origins.push(origin);
+    }
+
+    // Remove last active pair together with the rule which created it
+    pub fn pop_pair(&mut self) -> Option<((u64, u64), Origin)> {
+        let pair = self.active_pairs.pop()?;
+        Some((pair, self.origins.pop().flatten()))
+    }
+
     pub fn gen_id(&mut self) -> u64 {
-        self.cnt = self.cnt + 1;
-        return self.cnt;
+        // Prefer ids of dropped agents
+        if let Some(id) = self.heap.recycle() {
+            return id;
+        }
+        self.cnt += 1;
+        self.cnt
     }
 
-    // Create agent
-    pub fn new_agent(&mut self, id: u64, atype: u64) {
-        self.heap.insert(id, Agent{id: id, atype: atype, ports: vec![0]});
+    // Create agent with a principal port and arity auxiliary ports
+    pub fn new_agent(&mut self, id: u64, atype: u64, arity: u64) {
+        self.heap.insert(Agent { id, atype, ports: vec![FREE; arity as usize + 1], value: 0 });
     }
 
     // Remove agent
     pub fn drop_agent(&mut self, id: u64) {
-        // Unbind agent from all others
-        let local_agent = self.query_agent(id);
-        for port in &local_agent.ports {
-            self.unbind_agents(id, port.clone());
+        // Disconnect all wires which still end at this agent
+        for index in 0..self.agent(id).ports.len() {
+            self.unbind_agents((id, index as u64));
         }
         // Remove agent from heap
-        self.heap.remove(&id);
+        self.heap.remove(id);
     }
 
     // Get Agent
     pub fn query_agent(&self, aid: u64) -> Agent {
-        return self.heap.get(&aid).unwrap().clone();
+        self.agent(aid).clone()
+    }
+
+    // Borrow agent
+    pub fn agent(&self, aid: u64) -> &Agent {
+        self.heap.get(aid).unwrap()
     }
 
     // Get type of agent
     pub fn atype(&self, aid: u64) -> u64 {
-        return self.query_agent(aid).atype;
+        self.agent(aid).atype
     }
 
-    // Get arity of agent
+    // Get payload of agent
+    pub fn value(&self, aid: u64) -> u64 {
+        self.agent(aid).value
+    }
+
+    // Set payload of agent
+    pub fn set_value(&mut self, aid: u64, value: u64) {
+        self.heap.get_mut(aid).unwrap().value = value;
+    }
+
+    // Get number of auxiliary ports of agent
     pub fn arity(&self, aid: u64) -> u64 {
-        return self.query_agent(aid).ports.len() as u64;
+        self.agent(aid).ports.len() as u64 - 1
+    }
+
+    // Get endpoint a port is connected to
+    pub fn port(&self, port: Port) -> Port {
+        self.agent(port.0).ports[port.1 as usize]
+    }
+
+    // Set endpoint a port is connected to without touching the other side
+    fn set_port(&mut self, port: Port, endpoint: Port) {
+        self.heap.get_mut(port.0).unwrap().ports[port.1 as usize] = endpoint;
     }
 
-    // Connect two agents
-    pub fn bind_agents(&mut self, principals: (bool, bool), aid0: u64, aid1: u64) {
+    // Connect two ports
+    pub fn bind_agents(&mut self, port0: Port, port1: Port) {
         // Check for invalid id
-        if (aid0 == 0) || (aid1 == 0) {
+        if (port0.0 == 0) || (port1.0 == 0) {
             return;
         }
-        // Obtain local copies
-        let mut lcopy = self.heap.get(&aid0).unwrap().clone();
-        let mut rcopy = self.heap.get(&aid1).unwrap().clone();
-        // Add connection from aid0 to aid1
-        if principals.0 {
-            lcopy.ports[0] = aid1
-        } else {
-            lcopy.ports.push(aid1);
-        }
-        // Add connection from aid1 to aid0
-        if principals.1 {
-            rcopy.ports[0] = aid0
-        } else {
-            rcopy.ports.push(aid0);
-        }
+        // Add connection from port0 to port1
+        self.set_port(port0, port1);
+        // Add connection from port1 to port0
+        self.set_port(port1, port0);
         // Push active pair if new one gets created
-        if principals.0 && principals.1 {
-            self.active_pairs.push((aid0, aid1));
+        if port0.1 == 0 && port1.1 == 0 {
+            self.push_pair((port0.0, port1.0), self.origin);
         }
-        // Update Agents
-        self.heap.insert(lcopy.id, lcopy);
-        self.heap.insert(rcopy.id, rcopy);
     }
 
-    // Remove connection of two agents
-    pub fn unbind_agents(&mut self, aid0: u64, aid1: u64) {
+    // Disconnect port from whatever it is connected to
+    pub fn unbind_agents(&mut self, port: Port) {
         // Check for invalid id
-        if (aid0 == 0) || (aid1 == 0) {
+        if port.0 == 0 {
             return;
         }
-        // Obtain local copies
-        let mut lcopy = self.heap.get(&aid0).unwrap().clone();
-        let mut rcopy = self.heap.get(&aid1).unwrap().clone();
-        // Check if it is a principal connection
-        if lcopy.ports.get(0).unwrap().clone() == aid1 {
-            // In case overwrite principal port
-            lcopy.ports[0] = 0;
-        } else {
-            // If not delete auxiliary port
-            lcopy.ports.retain(|e| e.clone() != aid1);
-        }
-        // Check if it is a principal connection
-        if rcopy.ports.get(0).unwrap().clone() == aid0 {
-            // In case overwrite principal port
-            rcopy.ports[0] = 0;
-        } else {
-            // If not delete auxiliary port
-            rcopy.ports.retain(|e| e.clone() != aid0);
+        let other = self.port(port);
+        self.set_port(port, FREE);
+        // Other side may already be connected elsewhere
+        if other != FREE && self.port(other) == port {
+            self.set_port(other, FREE);
         }
-        // Update Agents
-        self.heap.insert(lcopy.id, lcopy);
-        self.heap.insert(rcopy.id, rcopy);
     }
-}
\ No newline at end of file
+
+    // Connect endpoint to whatever port is connected to and disconnect port
+    pub fn link(&mut self, endpoint: Port, port: Port) {
+        let other = self.port(port);
+        self.unbind_agents(port);
+        self.bind_agents(endpoint, other);
+    }
+}
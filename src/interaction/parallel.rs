@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::thread;
+use crate::interaction::*;
+use crate::interaction::heap::Heap;
+use crate::interaction::rules::RuleTable;
+use crate::interaction::verify::verify_local;
+use crate::interaction::vm::*;
+
+// Active pair selected for the current round
+struct Job {
+    pair: (u64, u64),
+    atypes: (u64, u64),
+    range: (usize, usize),  // Code of rule in shared segment
+    footprint: Vec<u64>,    // Agents the rule may touch
+    ids: Vec<u64>,          // Ids reserved for agents the rule creates
+}
+
+// Agents a rule for pair may read or write: the pair itself and its neighbours
+fn footprint(net: &InteractionNet, pair: (u64, u64)) -> Vec<u64> {
+    let mut ids = vec![pair.0, pair.1];
+    for id in [pair.0, pair.1] {
+        for port in &net.agent(id).ports {
+            if port.0 != 0 && !ids.contains(&port.0) {
+                ids.push(port.0);
+            }
+        }
+    }
+    ids
+}
+
+// Reduce jobs of one worker against the part of the net it owns
+fn work(rules: &RuleTable, local: &mut VM, jobs: &[Job]) {
+    for job in jobs {
+        // Push ids on vm stack
+        local.stack.push(job.pair.1);
+        local.stack.push(job.pair.0);
+        // Execute rule directly from shared code segment, new pairs remember it
+        local.interaction_net.origin = Some(job.atypes);
+        for pc in job.range.0..job.range.1 {
+            local.execute(rules.instruction(pc));
+        }
+        local.interaction_net.origin = None;
+    }
+}
+
+impl VM {
+
+    // Reduce interaction net with up to workers threads, returns active pairs there was no rule for.
+    // Every round reduces pairs whose footprints do not overlap, each worker owns the agents of its pairs.
+    // Rules which may reach beyond the footprint make it fall back to the sequential reducer
+    pub fn reduce_parallel(&mut self, workers: usize) -> Result<Vec<StuckPair>, ReduceError> {
+        if !self.rules.iter().all(|(_, code)| verify_local(code)) {
+            return self.reduce();
+        }
+        let workers = workers.max(1);
+        // Pairs without rule
+        let mut stuck = vec![];
+        // Vms of workers are kept across rounds, their heaps only hold the agents of a few footprints
+        let mut locals: Vec<VM> = (0..workers).map(|_| VM::new()).collect();
+        for local in &mut locals {
+            local.interaction_net.heap = Heap::sparse();
+        }
+
+        while !self.interaction_net.active_pairs.is_empty() {
+            let pairs: Vec<_> = std::mem::take(&mut self.interaction_net.active_pairs)
+                .into_iter()
+                .zip(std::mem::take(&mut self.interaction_net.origins))
+                .collect();
+
+            // 1. Select pairs which can be reduced independently, the others wait for the next round
+            let mut jobs = vec![];
+            let mut deferred = vec![];
+            let mut claimed = HashSet::new();
+            for &(pair, origin) in &pairs {
+                let atypes = (self.interaction_net.atype(pair.0), self.interaction_net.atype(pair.1));
+                let range = match self.rules.range(atypes) {
+                    Some(range) => range,
+                    None => {
+                        let sp = StuckPair { pair, atypes, origin };
+                        match self.policy {
+                            StuckPolicy::Error => {
+                                // Nothing has been reduced in this round, leave net as it was
+                                self.interaction_net.active_pairs = pairs.iter().map(|p| p.0).collect();
+                                self.interaction_net.origins = pairs.iter().map(|p| p.1).collect();
+                                return Err(ReduceError::NoRule(sp));
+                            }
+                            StuckPolicy::Collect | StuckPolicy::Keep => {
+                                stuck.push(sp);
+                            }
+                        }
+                        continue;
+                    }
+                };
+                let footprint = footprint(&self.interaction_net, pair);
+                if footprint.iter().any(|id| claimed.contains(id)) {
+                    deferred.push((pair, origin));
+                    continue;
+                }
+                claimed.extend(footprint.iter().copied());
+                jobs.push(Job { pair, atypes, range, footprint, ids: vec![] });
+            }
+
+            // 2. Rules are straight line code, so every GEN needs exactly one id
+            for job in &mut jobs {
+                let gens = (job.range.0..job.range.1)
+                    .filter(|pc| self.rules.instruction(*pc) == Instruction::GEN)
+                    .count();
+                job.ids = (0..gens).map(|_| self.interaction_net.gen_id()).collect();
+            }
+
+            // 3. Hand agents of every job over to its worker
+            let size = jobs.len().div_ceil(workers).max(1);
+            let chunks: Vec<&[Job]> = jobs.chunks(size).collect();
+            for (local, chunk) in locals.iter_mut().zip(&chunks) {
+                for job in chunk.iter() {
+                    for id in &job.footprint {
+                        let agent = self.interaction_net.heap.take(*id).unwrap();
+                        local.interaction_net.heap.insert(agent);
+                    }
+                    for id in &job.ids {
+                        local.interaction_net.heap.release(*id);
+                    }
+                }
+            }
+
+            // 4. Reduce
+            let rules = &self.rules;
+            if chunks.len() == 1 {
+                // Not worth spawning a thread
+                work(rules, &mut locals[0], chunks[0]);
+            } else {
+                thread::scope(|scope| {
+                    for (local, chunk) in locals.iter_mut().zip(&chunks) {
+                        scope.spawn(move || work(rules, local, chunk));
+                    }
+                });
+            }
+
+            // 5. Move agents back, deferred pairs stay in front like in the sequential reducer
+            for (pair, origin) in deferred {
+                self.interaction_net.push_pair(pair, origin);
+            }
+            for (local, chunk) in locals.iter_mut().zip(&chunks) {
+                for job in chunk.iter() {
+                    for id in job.footprint.iter().chain(job.ids.iter()) {
+                        if let Some(agent) = local.interaction_net.heap.take(*id) {
+                            self.interaction_net.heap.insert(agent);
+                        }
+                    }
+                }
+                // Ids of dropped agents and reserved ids which were not needed
+                while let Some(id) = local.interaction_net.heap.recycle() {
+                    self.interaction_net.heap.release(id);
+                }
+                self.interaction_net.active_pairs.append(&mut local.interaction_net.active_pairs);
+                self.interaction_net.origins.append(&mut local.interaction_net.origins);
+            }
+        }
+
+        // Put kept pairs back into net
+        if self.policy == StuckPolicy::Keep {
+            for sp in &stuck {
+                self.interaction_net.push_pair(sp.pair, sp.origin);
+            }
+        }
+        Ok(stuck)
+    }
+}
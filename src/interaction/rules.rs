@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use crate::interaction::stats::{kind, KINDS};
+use crate::interaction::vm::Instruction;
+
+// Agent types the dense index covers at most, rules of larger types are looked up in a map
+pub const DENSE_TYPES: usize = 256;
+
+// Where the code of a rule lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    start: usize,
+    end: usize,
+    kinds: usize, // Index into RuleTable::kinds
+}
+
+// Rewriting rules of all active pairs, code of every rule lives in one shared segment
+#[derive(Debug, Clone, Default)]
+pub struct RuleTable {
+    code: Vec<Instruction>,             // Code of all rules back to back
+    kinds: Vec<[u64; KINDS.len()]>,     // Instructions of a rule by kind, counted once for profiling
+    index: Vec<Option<Entry>>,          // Rule for (atype0, atype1) at atype0 * width + atype1
+    width: usize,                       // Number of agent types the index covers
+    sparse: HashMap<(u64, u64), Entry>, // Rules with a type of DENSE_TYPES or more
+    len: usize,                         // Number of rules
+}
+
+impl RuleTable {
+
+    pub fn new() -> Self {
+        RuleTable {
+            code: Vec::new(),
+            kinds: Vec::new(),
+            index: Vec::new(),
+            width: 0,
+            sparse: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    // Number of rules
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Position of rule in index
+    fn slot(&self, atypes: (u64, u64)) -> Option<usize> {
+        let (a, b) = (atypes.0 as usize, atypes.1 as usize);
+        if a < self.width && b < self.width {
+            Some(a * self.width + b)
+        } else {
+            None
+        }
+    }
+
+    // Make index cover agent types up to width
+    fn grow(&mut self, width: usize) {
+        let mut index = vec![None; width * width];
+        for a in 0..self.width {
+            for b in 0..self.width {
+                index[a * width + b] = self.index[a * self.width + b];
+            }
+        }
+        self.index = index;
+        self.width = width;
+    }
+
+    fn entry(&self, atypes: (u64, u64)) -> Option<Entry> {
+        match self.slot(atypes) {
+            Some(slot) => self.index[slot],
+            None => self.sparse.get(&atypes).copied(),
+        }
+    }
+
+    // Code range of rule for active pair
+    pub fn range(&self, atypes: (u64, u64)) -> Option<(usize, usize)> {
+        self.entry(atypes).map(|entry| (entry.start, entry.end))
+    }
+
+    // Instructions of rule for active pair by kind, indexed like stats::KINDS
+    pub fn kinds(&self, atypes: (u64, u64)) -> Option<&[u64; KINDS.len()]> {
+        self.entry(atypes).map(|entry| &self.kinds[entry.kinds])
+    }
+
+    // Instruction at position of shared code segment
+    pub fn instruction(&self, pc: usize) -> Instruction {
+        self.code[pc]
+    }
+
+    // Code of rule for active pair
+    pub fn get(&self, atypes: &(u64, u64)) -> Option<&[Instruction]> {
+        self.range(*atypes).map(|(start, end)| &self.code[start..end])
+    }
+
+    pub fn contains_key(&self, atypes: &(u64, u64)) -> bool {
+        self.range(*atypes).is_some()
+    }
+
+    // Adds rule, replaces existing rule for the same active pair
+    pub fn insert(&mut self, atypes: (u64, u64), instructions: Vec<Instruction>) {
+        let largest = atypes.0.max(atypes.1);
+        if largest < DENSE_TYPES as u64 && largest as usize >= self.width {
+            self.grow(largest as usize + 1);
+        }
+        let mut kinds = [0; KINDS.len()];
+        for instruction in &instructions {
+            kinds[kind(*instruction)] += 1;
+        }
+        let old = self.entry(atypes);
+        let entry = match old {
+            // Overwrite old code if new one fits exactly
+            Some(entry) if entry.end - entry.start == instructions.len() => {
+                self.code[entry.start..entry.end].copy_from_slice(&instructions);
+                self.kinds[entry.kinds] = kinds;
+                return;
+            }
+            _ => {
+                let start = self.code.len();
+                self.code.extend(instructions);
+                self.kinds.push(kinds);
+                Entry { start, end: self.code.len(), kinds: self.kinds.len() - 1 }
+            }
+        };
+        if old.is_none() {
+            self.len += 1;
+        }
+        match self.slot(atypes) {
+            Some(slot) => self.index[slot] = Some(entry),
+            None => {
+                self.sparse.insert(atypes, entry);
+            }
+        }
+    }
+
+    // Removes rule, its code stays in the segment
+    pub fn remove(&mut self, atypes: &(u64, u64)) {
+        let old = match self.slot(*atypes) {
+            Some(slot) => self.index[slot].take(),
+            None => self.sparse.remove(atypes),
+        };
+        if old.is_some() {
+            self.len -= 1;
+        }
+    }
+
+    // All rules with their active pair, ordered by types
+    pub fn iter(&self) -> impl Iterator<Item = ((u64, u64), &[Instruction])> {
+        let width = self.width;
+        let dense = self.index.iter().enumerate().filter_map(move |(slot, entry)| {
+            entry.map(|entry| (((slot / width) as u64, (slot % width) as u64), entry))
+        });
+        let sparse = self.sparse.iter().map(|(atypes, entry)| (*atypes, *entry));
+        let mut rules: Vec<_> = dense.chain(sparse).collect();
+        rules.sort_by_key(|(atypes, _)| *atypes);
+        rules.into_iter().map(move |(atypes, entry)| (atypes, &self.code[entry.start..entry.end]))
+    }
+}
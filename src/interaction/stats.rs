@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::interaction::InteractionNet;
+use crate::interaction::vm::Instruction;
+
+// Names of the kinds of instructions, Stats::instructions is indexed like this
+pub const KINDS: [&str; 15] = [
+    "NOP", "GEN", "CONST", "DUP", "PUSH", "POP", "NEW_AGENT", "DROP_AGENT",
+    "BIND", "UNBIND", "PORT", "LINK", "LOAD", "STORE", "OPERATE",
+];
+
+pub fn kind(instruction: Instruction) -> usize {
+    match instruction {
+        Instruction::NOP => 0,
+        Instruction::GEN => 1,
+        Instruction::CONST(_) => 2,
+        Instruction::DUP => 3,
+        Instruction::PUSH(_) => 4,
+        Instruction::POP(_) => 5,
+        Instruction::NEW_AGENT => 6,
+        Instruction::DROP_AGENT => 7,
+        Instruction::BIND => 8,
+        Instruction::UNBIND => 9,
+        Instruction::PORT => 10,
+        Instruction::LINK => 11,
+        Instruction::LOAD => 12,
+        Instruction::STORE => 13,
+        Instruction::OPERATE(_) => 14,
+    }
+}
+
+// Work done by one rule
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RuleStats {
+    pub interactions: u64,
+    pub instructions: u64,
+}
+
+// Counters of all reductions of a vm, reset by assigning Stats::default(). Interactions and peaks
+// are always counted, instructions and rules only while VM::profile is set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub interactions: u64,
+    pub instructions: [u64; KINDS.len()],      // Executed by rules, by kind
+    pub peak_heap: usize,                       // Most agents alive at once
+    pub peak_active_pairs: usize,               // Longest queue of active pairs
+    pub rules: HashMap<(u64, u64), RuleStats>,  // By types of active pair
+    pub time: Duration,
+}
+
+impl Stats {
+
+    // Rule for active pair of types atypes with instructions by kind is about to run, only counted
+    // when the vm profiles
+    pub fn profile(&mut self, atypes: (u64, u64), kinds: &[u64; KINDS.len()]) {
+        let rule = self.rules.entry(atypes).or_default();
+        rule.interactions += 1;
+        rule.instructions += kinds.iter().sum::<u64>();
+        for (count, add) in self.instructions.iter_mut().zip(kinds) {
+            *count += add;
+        }
+    }
+
+    // Take peaks of the current net
+    pub fn observe(&mut self, net: &InteractionNet) {
+        self.peak_heap = self.peak_heap.max(net.heap.len());
+        self.peak_active_pairs = self.peak_active_pairs.max(net.active_pairs.len());
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.instructions.iter().sum()
+    }
+
+    pub fn count(&self, instruction: Instruction) -> u64 {
+        self.instructions[kind(instruction)]
+    }
+
+    pub fn agents_created(&self) -> u64 {
+        self.count(Instruction::NEW_AGENT)
+    }
+
+    pub fn agents_dropped(&self) -> u64 {
+        self.count(Instruction::DROP_AGENT)
+    }
+
+    // Rules named after the type mapping, most instructions first
+    pub fn per_rule(&self, type_mapping: &HashMap<String, u64>) -> Vec<(String, String, RuleStats)> {
+        let names: HashMap<u64, &String> = type_mapping.iter().map(|(name, atype)| (*atype, name)).collect();
+        let name = |atype: u64| names.get(&atype).map(|name| name.to_string()).unwrap_or_else(|| atype.to_string());
+        let mut rules: Vec<_> = self.rules.iter()
+            .map(|((a, b), rule)| (name(*a), name(*b), *rule))
+            .collect();
+        rules.sort_by(|x, y| y.2.instructions.cmp(&x.2.instructions).then_with(|| (&x.0, &x.1).cmp(&(&y.0, &y.1))));
+        rules
+    }
+
+    // Counters as a table for printing after a run
+    pub fn table(&self, type_mapping: &HashMap<String, u64>) -> String {
+        let mut text = format!("{:<24} {:>12} {:>12}\n", "rule", "interactions", "instructions");
+        for (a, b, rule) in self.per_rule(type_mapping) {
+            text.push_str(&format!("{:<24} {:>12} {:>12}\n", format!("{} # {}", a, b), rule.interactions, rule.instructions));
+        }
+        text.push_str(&format!("{:<24} {:>12} {:>12}\n", "total", self.interactions, self.executed()));
+        text.push('\n');
+        text.push_str(&format!("{:<24} {:>12}\n", "instruction", "count"));
+        for (name, count) in KINDS.iter().zip(self.instructions) {
+            if count > 0 {
+                text.push_str(&format!("{:<24} {:>12}\n", name, count));
+            }
+        }
+        text.push('\n');
+        text.push_str(&format!("{:<24} {:>12}\n", "agents created", self.agents_created()));
+        text.push_str(&format!("{:<24} {:>12}\n", "agents dropped", self.agents_dropped()));
+        text.push_str(&format!("{:<24} {:>12}\n", "peak heap", self.peak_heap));
+        text.push_str(&format!("{:<24} {:>12}\n", "peak active pairs", self.peak_active_pairs));
+        text.push_str(&format!("{:<24} {:>12?}\n", "time", self.time));
+        text
+    }
+}
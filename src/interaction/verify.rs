@@ -0,0 +1,179 @@
+use std::fmt;
+use crate::interaction::vm::{Instruction, SCRATCHPAD_SIZE};
+
+// Rules start with the ids of the active pair on stack
+pub const RULE_DEPTH: usize = 2;
+
+// Why code would break the vm, pc is the offending instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    // Instruction fetches more values than the stack holds
+    Underflow { pc: usize, instruction: Instruction, depth: usize },
+    // Scratchpad address out of range
+    Address { pc: usize, instruction: Instruction },
+    // Values left on stack after the last instruction
+    Unbalanced { depth: usize },
+}
+
+impl fmt::Display for VerifyError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Underflow { pc, instruction, depth } => {
+                write!(f, "instruction {} `{}` needs more than the {} value(s) on stack", pc, instruction, depth)
+            }
+            VerifyError::Address { pc, instruction } => {
+                write!(f, "instruction {} `{}` is outside of the scratchpad of size {}", pc, instruction, SCRATCHPAD_SIZE)
+            }
+            VerifyError::Unbalanced { depth } => {
+                write!(f, "code leaves {} value(s) on stack", depth)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+// Number of values instruction fetches from stack and pushes on stack
+fn effect(instruction: Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::NOP => (0, 0),
+        Instruction::GEN | Instruction::CONST(_) | Instruction::PUSH(_) => (0, 1),
+        Instruction::DUP => (1, 2),
+        Instruction::POP(_) | Instruction::DROP_AGENT => (1, 0),
+        Instruction::NEW_AGENT => (3, 0),
+        Instruction::BIND | Instruction::LINK => (4, 0),
+        Instruction::UNBIND | Instruction::STORE => (2, 0),
+        Instruction::PORT => (2, 2),
+        Instruction::LOAD => (1, 1),
+        Instruction::OPERATE(_) => (2, 1),
+    }
+}
+
+// Check code running with depth values on stack, code is straight line so every instruction
+// sees the same stack on every run
+pub fn verify(code: &[Instruction], depth: usize) -> Result<(), VerifyError> {
+    let mut depth = depth;
+    for (pc, instruction) in code.iter().enumerate() {
+        if let Instruction::PUSH(addr) | Instruction::POP(addr) = instruction {
+            if *addr as usize >= SCRATCHPAD_SIZE {
+                return Err(VerifyError::Address { pc, instruction: *instruction });
+            }
+        }
+        let (fetched, pushed) = effect(*instruction);
+        if fetched > depth {
+            return Err(VerifyError::Underflow { pc, instruction: *instruction, depth });
+        }
+        depth = depth - fetched + pushed;
+    }
+    if depth != 0 {
+        return Err(VerifyError::Unbalanced { depth });
+    }
+    Ok(())
+}
+
+// Check code of a rule
+pub fn verify_rule(code: &[Instruction]) -> Result<(), VerifyError> {
+    verify(code, RULE_DEPTH)
+}
+
+// What a value on stack or in scratchpad is known to be while checking locality
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Own,        // Agent of the active pair or created by the rule, its ports only lead into the footprint
+    Neighbour,  // Agent connected to an own agent
+    Zero,       // Constant 0, ignored as agent id by BIND and UNBIND
+    Other,      // Anything else, e.g. port ids, values or agents further away
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().unwrap_or(Value::Other)
+}
+
+// Whether code of a rule touches nothing but the active pair, its neighbours and agents it creates,
+// which parallel reduction relies on. Code is expected to pass verify_rule
+pub fn verify_local(code: &[Instruction]) -> bool {
+    use Value::*;
+    let mut stack = vec![Own, Own];
+    let mut scratchpad = [Other; SCRATCHPAD_SIZE];
+    for instruction in code {
+        // Agent ids fetched by the instruction and which of them it may use
+        let (ids, allowed): (Vec<Value>, &[Value]) = match *instruction {
+            Instruction::NOP => (vec![], &[]),
+            Instruction::GEN => {
+                stack.push(Own);
+                (vec![], &[])
+            }
+            Instruction::CONST(c) => {
+                stack.push(if c == 0 { Zero } else { Other });
+                (vec![], &[])
+            }
+            Instruction::DUP => {
+                let top = pop(&mut stack);
+                stack.extend([top, top]);
+                (vec![], &[])
+            }
+            Instruction::PUSH(addr) => {
+                stack.push(scratchpad[addr as usize % SCRATCHPAD_SIZE]);
+                (vec![], &[])
+            }
+            Instruction::POP(addr) => {
+                scratchpad[addr as usize % SCRATCHPAD_SIZE] = pop(&mut stack);
+                (vec![], &[])
+            }
+            Instruction::NEW_AGENT => {
+                pop(&mut stack);
+                pop(&mut stack);
+                (vec![pop(&mut stack)], &[Own])
+            }
+            // Dropping an agent disconnects whatever its ports lead to
+            Instruction::DROP_AGENT => (vec![pop(&mut stack)], &[Own]),
+            Instruction::BIND => {
+                pop(&mut stack);
+                let aid1 = pop(&mut stack);
+                pop(&mut stack);
+                (vec![pop(&mut stack), aid1], &[Own, Neighbour, Zero])
+            }
+            Instruction::UNBIND => {
+                pop(&mut stack);
+                (vec![pop(&mut stack)], &[Own, Zero])
+            }
+            Instruction::PORT => {
+                pop(&mut stack);
+                let aid = pop(&mut stack);
+                let other = if aid == Own { Neighbour } else { Other };
+                stack.extend([other, Other]);
+                (vec![aid], &[Own, Neighbour])
+            }
+            Instruction::LINK => {
+                pop(&mut stack);
+                let aid = pop(&mut stack);
+                pop(&mut stack);
+                let eaid = pop(&mut stack);
+                if !matches!(eaid, Own | Neighbour | Zero) {
+                    return false;
+                }
+                (vec![aid], &[Own])
+            }
+            Instruction::LOAD => {
+                let id = pop(&mut stack);
+                stack.push(Other);
+                (vec![id], &[Own, Neighbour])
+            }
+            Instruction::STORE => {
+                pop(&mut stack);
+                (vec![pop(&mut stack)], &[Own, Neighbour])
+            }
+            Instruction::OPERATE(_) => {
+                pop(&mut stack);
+                pop(&mut stack);
+                stack.push(Other);
+                (vec![], &[])
+            }
+        };
+        if !ids.iter().all(|id| allowed.contains(id)) {
+            return false;
+        }
+    }
+    true
+}
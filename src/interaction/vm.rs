@@ -1,11 +1,19 @@
-use std::ops::Index;
-use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::interaction::*;
+use crate::interaction::rules::RuleTable;
+use crate::interaction::verify::{verify_rule, VerifyError};
+use crate::interaction::stats::Stats;
 
 pub const SCRATCHPAD_SIZE: usize = 256;
 
-#[derive(Debug, Clone)]
+// Most auxiliary ports an agent created by checked code may have
+pub const MAX_ARITY: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     NOP, // No operation
     GEN, // Pushes newly generated id on stack
@@ -13,21 +21,156 @@ pub enum Instruction {
     DUP, // Duplicate stack top
     PUSH(u64), // Load from static memory address
     POP(u64), // Store from static memory address
-    NEW_AGENT, // Creates new agent on heap
+    NEW_AGENT, // Fetches id, type and arity from stack and creates new agent on heap
     DROP_AGENT, // Deletes agent from heap
-    BIND, // Connects two agents
-    UNBIND, // Removes connection between agents
-    PORT, // Fetches agent id and port id from stack and pushes agents[aid].ports[pid] on stack
+    BIND, // Fetches two ports (agent id, port id) from stack and connects them
+    UNBIND, // Fetches port from stack and removes its connection
+    PORT, // Fetches port from stack and pushes agent id and port id it is connected to on stack
+    LINK, // Fetches port and endpoint from stack and moves connection of port to endpoint
+    LOAD, // Fetches agent id from stack and pushes its value
+    STORE, // Fetches agent id and value from stack and sets value of agent
+    OPERATE(Operator), // Fetches two values from stack and pushes result of operator
+}
+
+// Arithmetic on values of agents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,    // Stops at 0
+    Mul,
+    Less,   // 1 if first value is less than second, 0 otherwise
+    Equal,  // 1 if values are equal, 0 otherwise
+}
+
+// All operators
+pub const OPERATORS: [Operator; 5] = [Operator::Add, Operator::Sub, Operator::Mul, Operator::Less, Operator::Equal];
+
+impl Operator {
+
+    // Symbol of operator in programs and assembly
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Less => "<",
+            Operator::Equal => "==",
+        }
+    }
+
+    // Result of first value and second value, numbers wrap around on overflow
+    pub fn apply(self, a: u64, b: u64) -> u64 {
+        match self {
+            Operator::Add => a.wrapping_add(b),
+            Operator::Sub => a.saturating_sub(b),
+            Operator::Mul => a.wrapping_mul(b),
+            Operator::Less => (a < b) as u64,
+            Operator::Equal => (a == b) as u64,
+        }
+    }
+}
+
+// What to do with active pairs there is no rule for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StuckPolicy {
+    Error,      // Stop reduction with an error
+    Collect,    // Remove pair from net and return it from reduction
+    Keep,       // Return pair from reduction but leave it active in net
+}
+
+// Active pair there is no rule for
+#[derive(Debug, Clone, PartialEq)]
+pub struct StuckPair {
+    pub pair: (u64, u64),
+    pub atypes: (u64, u64),
+    pub origin: Origin, // Rule which created the pair, None for the initial net
+}
+
+// Instruction of checked code which would break the net, e.g. it uses an agent that does not exist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault {
+    pub pc: usize,
+    pub instruction: Instruction,
+}
+
+impl fmt::Display for Fault {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction {} `{}` uses agents or ports which do not exist", self.pc, self.instruction)
+    }
+}
+
+impl std::error::Error for Fault {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReduceError {
+    NoRule(StuckPair),
+}
+
+// Stops a reduction running elsewhere, checked between interactions
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// How far a reduction may go, None means no limit
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub interactions: Option<u64>,  // Rules to run
+    pub instructions: Option<u64>,  // Instructions executed by these rules
+    pub cancel: Option<CancelHandle>,
+}
+
+// Why reduction stopped, every variant carries the active pairs there was no rule for.
+// Unless normal form was reached these pairs stay in the net whatever the StuckPolicy is,
+// so reducing again resumes and reports them again
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Normal(Vec<StuckPair>),
+    Exhausted(Vec<StuckPair>),
+    Cancelled(Vec<StuckPair>),
+}
+
+impl Outcome {
+
+    pub fn stuck(&self) -> &[StuckPair] {
+        match self {
+            Outcome::Normal(stuck) | Outcome::Exhausted(stuck) | Outcome::Cancelled(stuck) => stuck,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct VM {
     pub pc: usize,
     pub code: Vec<Instruction>,
-    pub stack: Vec<u64>, // Stack does only carry agent ids
+    pub stack: Vec<u64>, // Stack does only carry agent ids, port ids and constants
     pub scratchpad: [u64; SCRATCHPAD_SIZE],
     pub interaction_net: InteractionNet,
-    pub rules: HashMap<(u64, u64), Vec<Instruction>>,
+    pub rules: RuleTable,
+    pub policy: StuckPolicy,
+    pub interface: HashMap<String, u64>, // Boundary agent of every free variable of the initial net
+    pub stats: Stats, // Collected by sequential reduction
+    pub profile: bool, // Count executed instructions and work of every rule in stats
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
@@ -39,13 +182,24 @@ impl VM {
             stack: Vec::new(),
             scratchpad: [0; SCRATCHPAD_SIZE],
             interaction_net: InteractionNet::new(),
-            rules: HashMap::new(),
+            rules: RuleTable::new(),
+            policy: StuckPolicy::Collect,
+            interface: HashMap::new(),
+            stats: Stats::default(),
+            profile: false,
         }
     }
 
     // Execute single instruction
     pub fn step(&mut self) {
-        match self.code.get(self.pc).unwrap().clone() {
+        self.execute(self.code[self.pc]);
+        // Increment pc
+        self.pc += 1;
+    }
+
+    // Execute instruction regardless of where it is stored
+    pub fn execute(&mut self, instruction: Instruction) {
+        match instruction {
             Instruction::NOP => {
                 // Do nothing
             }
@@ -56,43 +210,65 @@ impl VM {
                 self.stack.push(c);
             }
             Instruction::DUP => {
-                self.stack.push(self.stack.last().unwrap().clone());
+                self.stack.push(*self.stack.last().unwrap());
             }
             Instruction::PUSH(addr) => {
                 self.stack.push(self.scratchpad[addr as usize]);
             }
             Instruction::POP(addr) => {
-                self.scratchpad[addr as usize] = self.stack.pop().unwrap().clone();
+                self.scratchpad[addr as usize] = self.stack.pop().unwrap();
             }
             Instruction::NEW_AGENT => {
-                let atype = self.stack.pop().unwrap().clone();
-                let id = self.stack.pop().unwrap().clone();
-                self.interaction_net.new_agent(id, atype);
+                let arity = self.stack.pop().unwrap();
+                let atype = self.stack.pop().unwrap();
+                let id = self.stack.pop().unwrap();
+                self.interaction_net.new_agent(id, atype, arity);
             }
             Instruction::DROP_AGENT => {
-                let id = self.stack.pop().unwrap().clone();
+                let id = self.stack.pop().unwrap();
                 self.interaction_net.drop_agent(id);
             }
             Instruction::BIND => {
-                let prin1 = self.stack.pop().unwrap().clone();
-                let prin0 = self.stack.pop().unwrap().clone();
-                let aid1 = self.stack.pop().unwrap().clone();
-                let aid0 = self.stack.pop().unwrap().clone();
-                self.interaction_net.bind_agents((prin0 > 0, prin1 > 0), aid0, aid1);
+                let pid1 = self.stack.pop().unwrap();
+                let aid1 = self.stack.pop().unwrap();
+                let pid0 = self.stack.pop().unwrap();
+                let aid0 = self.stack.pop().unwrap();
+                self.interaction_net.bind_agents((aid0, pid0), (aid1, pid1));
             }
             Instruction::UNBIND => {
-                let aid1 = self.stack.pop().unwrap().clone();
-                let aid0 = self.stack.pop().unwrap().clone();
-                self.interaction_net.unbind_agents(aid0, aid1);
+                let pid = self.stack.pop().unwrap();
+                let aid = self.stack.pop().unwrap();
+                self.interaction_net.unbind_agents((aid, pid));
             },
             Instruction::PORT => {
-                let pid = self.stack.pop().unwrap().clone();
-                let aid = self.stack.pop().unwrap().clone();
-                self.stack.push(self.interaction_net.query_agent(aid).ports[pid as usize]);
+                let pid = self.stack.pop().unwrap();
+                let aid = self.stack.pop().unwrap();
+                let other = self.interaction_net.port((aid, pid));
+                self.stack.push(other.0);
+                self.stack.push(other.1);
+            }
+            Instruction::LINK => {
+                let pid = self.stack.pop().unwrap();
+                let aid = self.stack.pop().unwrap();
+                let epid = self.stack.pop().unwrap();
+                let eaid = self.stack.pop().unwrap();
+                self.interaction_net.link((eaid, epid), (aid, pid));
+            }
+            Instruction::LOAD => {
+                let id = self.stack.pop().unwrap();
+                self.stack.push(self.interaction_net.value(id));
+            }
+            Instruction::STORE => {
+                let value = self.stack.pop().unwrap();
+                let id = self.stack.pop().unwrap();
+                self.interaction_net.set_value(id, value);
+            }
+            Instruction::OPERATE(operator) => {
+                let b = self.stack.pop().unwrap();
+                let a = self.stack.pop().unwrap();
+                self.stack.push(operator.apply(a, b));
             }
         }
-        // Increment pc
-        self.pc = self.pc + 1;
     }
 
     // Execute whole program
@@ -102,9 +278,77 @@ impl VM {
         }
     }
 
-    // Adds new rewriting rule
-    pub fn new_rewrite(&mut self, atypes: (u64, u64), instructions: Vec<Instruction>) {
+    // Port exists on an agent of the net
+    fn exists(&self, port: Port) -> bool {
+        self.interaction_net.heap.get(port.0).is_some_and(|agent| (port.1 as usize) < agent.ports.len())
+    }
+
+    // Port exists and so does the other end of its wire, id 0 is ignored like by the net
+    fn linked(&self, port: Port) -> bool {
+        if port.0 == 0 {
+            return true;
+        }
+        if !self.exists(port) {
+            return false;
+        }
+        let other = self.interaction_net.port(port);
+        other == FREE || self.exists(other)
+    }
+
+    // Whether instruction can be executed without breaking the vm
+    fn check(&self, instruction: Instruction) -> bool {
+        // n-th value from the top of stack
+        let at = |n: usize| self.stack.len().checked_sub(n + 1).map(|i| self.stack[i]);
+        let net = &self.interaction_net;
+        let port = |n: usize| Some((at(n + 1)?, at(n)?));
+        match instruction {
+            Instruction::NOP | Instruction::GEN | Instruction::CONST(_) => true,
+            Instruction::DUP => at(0).is_some(),
+            Instruction::PUSH(addr) => (addr as usize) < SCRATCHPAD_SIZE,
+            Instruction::POP(addr) => (addr as usize) < SCRATCHPAD_SIZE && at(0).is_some(),
+            Instruction::NEW_AGENT => match (at(2), at(0)) {
+                // Ids handed out by GEN only, so the heap grows with the code
+                (Some(id), Some(arity)) => id != 0 && id <= net.cnt && !net.heap.contains(id) && arity <= MAX_ARITY,
+                _ => false,
+            },
+            Instruction::DROP_AGENT | Instruction::LOAD => at(0).is_some_and(|id| net.heap.contains(id)),
+            Instruction::STORE => at(1).is_some_and(|id| net.heap.contains(id)),
+            Instruction::BIND => match (port(2), port(0)) {
+                (Some(port0), Some(port1)) => {
+                    [port0, port1].iter().all(|port| port.0 == 0 || self.exists(*port))
+                }
+                _ => false,
+            },
+            Instruction::UNBIND => port(0).is_some_and(|port| self.linked(port)),
+            Instruction::PORT => port(0).is_some_and(|port| self.exists(port)),
+            Instruction::LINK => match (port(2), port(0)) {
+                (Some(endpoint), Some(port)) => {
+                    self.linked(port) && (endpoint.0 == 0 || self.exists(endpoint))
+                }
+                _ => false,
+            },
+            Instruction::OPERATE(_) => at(1).is_some(),
+        }
+    }
+
+    // Execute whole program, stops before the first instruction which would break the vm.
+    // For code from untrusted sources, checking every instruction costs time
+    pub fn run_checked(&mut self) -> Result<(), Fault> {
+        while self.pc < self.code.len() {
+            let instruction = self.code[self.pc];
+            if !self.check(instruction) {
+                return Err(Fault { pc: self.pc, instruction });
+            }
+            self.step();
+        }
+        Ok(())
+    }
+
+    // Adds new rewriting rule, code which would break the vm is rejected
+    pub fn new_rewrite(&mut self, atypes: (u64, u64), instructions: Vec<Instruction>) -> Result<(), VerifyError> {
+        verify_rule(&instructions)?;
         self.rules.insert(atypes, instructions);
+        Ok(())
     }
 
     // Removes rewriting rule
@@ -112,25 +356,101 @@ impl VM {
         self.rules.remove(&atypes);
     }
 
-    // Reduce interaction net
-    pub fn reduce(&mut self) {
-        while self.interaction_net.active_pairs.len() > 0 {
+    // Reduce interaction net, returns active pairs there was no rule for
+    pub fn reduce(&mut self) -> Result<Vec<StuckPair>, ReduceError> {
+        match self.reduce_with_budget(&Budget::default())? {
+            Outcome::Normal(stuck) => Ok(stuck),
+            _ => unreachable!("reduction without budget stopped early"),
+        }
+    }
+
+    // Reduce interaction net running at most max_interactions rules
+    pub fn reduce_with_limit(&mut self, max_interactions: u64) -> Result<Outcome, ReduceError> {
+        self.reduce_with_budget(&Budget { interactions: Some(max_interactions), ..Budget::default() })
+    }
+
+    // Prepare running the rule for active pair of types atypes, which has to exist
+    pub fn begin_interaction(&mut self, pair: (u64, u64), atypes: (u64, u64)) {
+        self.stats.interactions += 1;
+        if self.profile {
+            self.stats.profile(atypes, self.rules.kinds(atypes).unwrap());
+        }
+        // Push ids on vm stack
+        self.stack.push(pair.1);
+        self.stack.push(pair.0);
+        // New pairs remember the rule
+        self.interaction_net.origin = Some(atypes);
+    }
+
+    // Rule started by begin_interaction has run
+    pub fn end_interaction(&mut self) {
+        self.interaction_net.origin = None;
+        self.stats.observe(&self.interaction_net);
+    }
+
+    // Reduce interaction net until normal form is reached, budget is used up or it is cancelled.
+    // Rules are never interrupted, one which does not fit into the instruction budget is not run
+    pub fn reduce_with_budget(&mut self, budget: &Budget) -> Result<Outcome, ReduceError> {
+        let started = Instant::now();
+        self.stats.observe(&self.interaction_net);
+        // Pairs without rule
+        let mut stuck = vec![];
+        let mut interactions = 0;
+        let mut instructions = 0;
+        let outcome: fn(Vec<StuckPair>) -> Outcome = loop {
+            if self.interaction_net.active_pairs.is_empty() {
+                break Outcome::Normal;
+            }
+            if budget.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+                break Outcome::Cancelled;
+            }
+            if budget.interactions.is_some_and(|max| interactions >= max) {
+                break Outcome::Exhausted;
+            }
             // Fetch active pair
-            let pair = self.interaction_net.active_pairs.pop().unwrap().clone();
-            // Push ids on vm stack
-            self.stack.push(pair.1);
-            self.stack.push(pair.0);
+            let (pair, origin) = self.interaction_net.pop_pair().unwrap();
             // Fetch rule for currently active pair
-            let program = self.rules.get(
-                &(self.interaction_net.atype(pair.0), self.interaction_net.atype(pair.1))
-            );
-            if program.is_some() {
-                // Load rewriting instructions for rule
-                self.code = program.unwrap().clone();
-                self.pc = 0;
-                // Execute rule
-                self.run();
+            let atypes = (self.interaction_net.atype(pair.0), self.interaction_net.atype(pair.1));
+            let (start, end) = match self.rules.range(atypes) {
+                Some(range) => range,
+                None => {
+                    let sp = StuckPair { pair, atypes, origin };
+                    match self.policy {
+                        StuckPolicy::Error => {
+                            // Leave net as it was
+                            self.interaction_net.push_pair(pair, origin);
+                            return Err(ReduceError::NoRule(sp));
+                        }
+                        StuckPolicy::Collect | StuckPolicy::Keep => {
+                            stuck.push(sp);
+                        }
+                    }
+                    continue;
+                }
+            };
+            let length = (end - start) as u64;
+            if budget.instructions.is_some_and(|max| instructions + length > max) {
+                // Leave pair for the next reduction
+                self.interaction_net.push_pair(pair, origin);
+                break Outcome::Exhausted;
+            }
+            interactions += 1;
+            instructions += length;
+            self.begin_interaction(pair, atypes);
+            // Execute rule directly from shared code segment
+            for pc in start..end {
+                self.execute(self.rules.instruction(pc));
+            }
+            self.end_interaction();
+        };
+        self.stats.time += started.elapsed();
+        let outcome = outcome(stuck);
+        // Put kept pairs back into net in their old order, collected ones too when reduction is resumed later
+        if self.policy == StuckPolicy::Keep || !matches!(outcome, Outcome::Normal(_)) {
+            for sp in outcome.stuck().iter().rev() {
+                self.interaction_net.push_pair(sp.pair, sp.origin);
             }
         }
+        Ok(outcome)
     }
 }
\ No newline at end of file
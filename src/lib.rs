@@ -0,0 +1,5 @@
+// Names like compileProgram, Semantic and NEW_AGENT are the vocabulary of the language and its vm
+#![allow(non_snake_case, non_camel_case_types)]
+
+pub mod parser;
+pub mod interaction;
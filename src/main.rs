@@ -1,31 +1,114 @@
-extern crate pest;
-#[macro_use]
-extern crate pest_derive;
-
-mod parser;
-mod interaction;
-
-use crate::parser::{ast, AstNode, LangParser, Semantic};
-use crate::parser::transform::compileProgram;
+use YAIN::parser::{ast, Semantic};
+use YAIN::parser::transform::{compileProgram, rule_equation};
+use YAIN::parser::error::snippet;
+use YAIN::parser::readback::{readback_interface, roots, Reader};
+use YAIN::interaction::debug::Debugger;
+use YAIN::interaction::dot::frames;
 
 fn main() {
 
     let s = "
     ADD(x) # S(y) = ADD(S(x)) ~ y
-    ADD(O) # O() = O
     ADD(x) # O() = x
     ADD(O) ~ S(O)".to_string();
 
-    let an = ast(&s);
+    let an = match ast(&s) {
+        Ok(an) => an,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     println!("AST: {:?}", an.clone());
 
-    let pg = Semantic::Program::fromAst(an.clone());
+    let pg = match Semantic::Program::fromAst(an.clone()) {
+        Ok(pg) => pg,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     println!("{:?}", pg);
 
-    let mut compiled = compileProgram(pg);
+    let mut compiled = match compileProgram(pg.clone()) {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            eprintln!("{}\n{}", e, snippet(&s, e.span()));
+            std::process::exit(1);
+        }
+    };
     println!("{:?}", compiled.1);
 
-    compiled.0.reduce();
+    // Name of agent type
+    let name = |atype: u64| {
+        compiled.1.iter().find(|(_, t)| **t == atype).map(|(n, _)| n.clone()).unwrap_or_default()
+    };
+
+    // Instructions are only counted when asked for
+    compiled.0.profile = std::env::args().any(|arg| arg == "--stats");
+
+    // Reduction can be followed step by step, the vm finishes whatever is left afterwards
+    if std::env::args().any(|arg| arg == "--debug") {
+        let mut debugger = Debugger::new(&mut compiled.0, &compiled.1);
+        if let Err(e) = debugger.repl(&mut std::io::stdin().lock(), &mut std::io::stdout()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Every step of the reduction as a DOT graph, rendered e.g. with `dot -Tpng -O DIR/*.dot`
+    let args: Vec<String> = std::env::args().collect();
+    let mut stuck = vec![];
+    if let Some(dir) = args.iter().position(|arg| arg == "--dot").and_then(|i| args.get(i + 1)) {
+        let frames = match frames(&mut compiled.0, &compiled.1, None) {
+            Ok((frames, pairs)) => {
+                stuck = pairs;
+                frames
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        };
+        for (i, frame) in frames.iter().enumerate() {
+            let path = std::path::Path::new(dir).join(format!("frame_{:04}.dot", i));
+            if let Err(e) = std::fs::write(&path, frame) {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Pairs left by the frames are reported together with the rest
+    match compiled.0.reduce() {
+        Ok(pairs) => stuck.extend(pairs),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    }
+    for sp in stuck {
+        eprintln!("no rule for {} # {}", name(sp.atypes.0), name(sp.atypes.1));
+        // Point to rule which created the pair
+        if let Some(equation) = sp.origin.and_then(|rule| rule_equation(&pg, &compiled.1, rule)) {
+            eprintln!("{}", snippet(&s, equation.span));
+        }
+    }
 
-    println!("{:?}", compiled.0.interaction_net.heap);
+    let vm = &compiled.0;
+    if std::env::args().any(|arg| arg == "--stats") {
+        eprint!("{}", vm.stats.table(&compiled.1));
+    }
+    // Print what is connected to the interface of the term
+    let mut names: Vec<&String> = vm.interface.keys().collect();
+    names.sort();
+    for name in names {
+        println!("{} = {}", name, readback_interface(vm, &compiled.1, name).unwrap());
+    }
+    // Print every other result as a term
+    for root in roots(&vm.interaction_net) {
+        if !vm.interface.values().any(|id| *id == root) {
+            println!("{}", Reader::new(&vm.interaction_net, &compiled.1).with_interface(&vm.interface).agent(root));
+        }
+    }
 }
\ No newline at end of file
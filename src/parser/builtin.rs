@@ -0,0 +1,111 @@
+use crate::parser::*;
+use crate::parser::check::infer_arities;
+
+// Eraser, deletes every agent it meets
+pub const ERA: &str = "ERA";
+// Duplicator, copies every agent it meets
+pub const DUP: &str = "DUP";
+
+pub fn var(name: String) -> Semantic::Agent {
+    Semantic::Agent { name, atype: AgentType::Var, ports: vec![], span: Span::default() }
+}
+
+pub fn agent(name: &str, ports: Vec<Semantic::Agent>) -> Semantic::Agent {
+    Semantic::Agent { name: name.to_string(), atype: AgentType::Agent, ports, span: Span::default() }
+}
+
+pub fn connect(left: Semantic::Agent, right: Semantic::Agent) -> Semantic::Principal {
+    Semantic::Principal { ptype: PrincipalType::Pure, left, right, span: Span::default() }
+}
+
+// Agent with fresh variables x1, ..., xn on its auxiliary ports
+fn pattern(name: &str, arity: usize) -> Semantic::Agent {
+    agent(name, (1..=arity).map(|i| var(format!("x{}", i))).collect())
+}
+
+// ERA() # A(x1, ..., xn) = ERA() ~ x1, ..., ERA() ~ xn
+fn erase(name: &str, arity: usize) -> Semantic::Equation {
+    Semantic::Equation {
+        left: agent(ERA, vec![]),
+        right: pattern(name, arity),
+        principals: (1..=arity).map(|i| connect(agent(ERA, vec![]), var(format!("x{}", i)))).collect(),
+        span: Span::default(),
+    }
+}
+
+// DUP(a, b) # DUP(c, d) = a ~ c, b ~ d
+pub fn annihilate(dup: &str) -> Semantic::Equation {
+    Semantic::Equation {
+        left: agent(dup, vec![var("a".to_string()), var("b".to_string())]),
+        right: agent(dup, vec![var("c".to_string()), var("d".to_string())]),
+        principals: vec![
+            connect(var("a".to_string()), var("c".to_string())),
+            connect(var("b".to_string()), var("d".to_string())),
+        ],
+        span: Span::default(),
+    }
+}
+
+// DUP(a, b) # A(x1, ..., xn) = A(a1, ..., an) ~ a, A(b1, ..., bn) ~ b, DUP(a1, b1) ~ x1, ..., DUP(an, bn) ~ xn
+pub fn commute(dup: &str, name: &str, arity: usize) -> Semantic::Equation {
+    let copy = |prefix: &str| agent(name, (1..=arity).map(|i| var(format!("{}{}", prefix, i))).collect());
+    let mut principals = vec![
+        connect(copy("a"), var("a".to_string())),
+        connect(copy("b"), var("b".to_string())),
+    ];
+    for i in 1..=arity {
+        principals.push(connect(
+            agent(dup, vec![var(format!("a{}", i)), var(format!("b{}", i))]),
+            var(format!("x{}", i))));
+    }
+    Semantic::Equation {
+        left: agent(dup, vec![var("a".to_string()), var("b".to_string())]),
+        right: pattern(name, arity),
+        principals,
+        span: Span::default(),
+    }
+}
+
+// Add signatures and rules of built-in agents the program uses,
+// rules written by the user take precedence over generated ones
+pub fn with_builtins(program: &Semantic::Program) -> Semantic::Program {
+    let mut program = program.clone();
+    let arities = infer_arities(&program).0;
+
+    // Agents in a fixed order, so generated rules do not depend on hashing
+    let mut names: Vec<&String> = arities.keys().collect();
+    names.sort();
+
+    // Pair already has a rule
+    let defined = |program: &Semantic::Program, a: &str, b: &str| {
+        program.equations.iter().any(|eq| {
+            (eq.left.name == a && eq.right.name == b) || (eq.left.name == b && eq.right.name == a)
+        })
+    };
+
+    let mut generated = vec![];
+    if arities.contains_key(ERA) {
+        program.signatures.insert(0, Semantic::Signature { name: ERA.to_string(), arity: 0, span: Span::default() });
+        for name in &names {
+            if !defined(&program, ERA, name) {
+                generated.push(erase(name, arities[*name]));
+            }
+        }
+    }
+    if arities.contains_key(DUP) {
+        program.signatures.insert(0, Semantic::Signature { name: DUP.to_string(), arity: 2, span: Span::default() });
+        for name in &names {
+            // Eraser meeting duplicator is already covered
+            if name.as_str() == ERA || defined(&program, DUP, name) {
+                continue;
+            }
+            if name.as_str() == DUP {
+                generated.push(annihilate(DUP));
+            } else {
+                generated.push(commute(DUP, name, arities[*name]));
+            }
+        }
+    }
+    program.equations.append(&mut generated);
+    program
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::parser::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    // Variable of the active pair does not occur in the result
+    Unused {
+        name: String,
+        span: Span,
+    },
+    // Variable of the result does not occur in the active pair
+    Unbound {
+        name: String,
+        span: Span,
+    },
+    // Variable occurs more than once on the same side of an equation
+    Repeated {
+        name: String,
+        spans: Vec<Span>,
+    },
+    // Variable is nested too deep in the active pair to be bound
+    Nested {
+        name: String,
+        span: Span,
+    },
+    // Agent is used with a different number of ports than declared or used before
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+        origin: Span,
+    },
+    // Agent is declared twice with different arities
+    Redeclared {
+        name: String,
+        span: Span,
+        origin: Span,
+    },
+    // Active pair already has a different rule (in either order)
+    Conflict {
+        left: String,
+        right: String,
+        span: Span,
+        origin: Span,
+    },
+}
+
+impl Violation {
+
+    // Location of the first offending occurrence
+    pub fn span(&self) -> Span {
+        match self {
+            Violation::Unused { span, .. } => *span,
+            Violation::Unbound { span, .. } => *span,
+            Violation::Repeated { spans, .. } => spans[0],
+            Violation::Nested { span, .. } => *span,
+            Violation::Arity { span, .. } => *span,
+            Violation::Redeclared { span, .. } => *span,
+            Violation::Conflict { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(f, "{}:{}: ", span.line, span.col)?;
+        match self {
+            Violation::Unused { name, .. } => {
+                write!(f, "variable `{}` is never used in the result", name)
+            }
+            Violation::Unbound { name, .. } => {
+                write!(f, "variable `{}` is not bound by the active pair", name)
+            }
+            Violation::Repeated { name, spans } => {
+                write!(f, "variable `{}` occurs {} times on the same side", name, spans.len())
+            }
+            Violation::Nested { name, .. } => {
+                write!(f, "variable `{}` has to be a direct port of the active pair", name)
+            }
+            // Built-in agents have no location
+            Violation::Arity { name, expected, found, origin, .. } if *origin == Span::default() => {
+                write!(f, "agent `{}` used with {} port(s) but is built in with arity {}", name, found, expected)
+            }
+            Violation::Arity { name, expected, found, origin, .. } => {
+                write!(f, "agent `{}` used with {} port(s) but has arity {} (see {}:{})",
+                       name, found, expected, origin.line, origin.col)
+            }
+            Violation::Redeclared { name, origin, .. } if *origin == Span::default() => {
+                write!(f, "agent `{}` is built in with another arity", name)
+            }
+            Violation::Redeclared { name, origin, .. } => {
+                write!(f, "agent `{}` already declared with another arity (see {}:{})",
+                       name, origin.line, origin.col)
+            }
+            Violation::Conflict { left, right, origin, .. } => {
+                write!(f, "rule for `{} # {}` conflicts with rule at {}:{}",
+                       left, right, origin.line, origin.col)
+            }
+        }
+    }
+}
+
+// Collect occurrences of variables in agent and its sub agents
+fn occurrences(agent: &Semantic::Agent, found: &mut Vec<(String, Span)>) {
+    if agent.atype == AgentType::Var {
+        found.push((agent.name.clone(), agent.span));
+    }
+    for port in &agent.ports {
+        occurrences(port, found);
+    }
+}
+
+// Every variable of an equation has to occur exactly once in the ports of the
+// active pair and exactly once in the result, or exactly twice in the result only
+pub fn check_equation(equation: &Semantic::Equation) -> Vec<Violation> {
+
+    let mut violations = vec![];
+
+    // Variables bound by the active pair
+    let mut bound = vec![];
+    for port in equation.left.ports.iter().chain(equation.right.ports.iter()) {
+        if port.atype == AgentType::Var {
+            bound.push((port.name.clone(), port.span));
+        } else {
+            // Patterns deeper down can not be bound by the compiled rule
+            let mut nested = vec![];
+            occurrences(port, &mut nested);
+            for (name, span) in nested {
+                violations.push(Violation::Nested { name, span });
+            }
+        }
+    }
+
+    // Variables used by the result
+    let mut used = vec![];
+    for principal in &equation.principals {
+        occurrences(&principal.left, &mut used);
+        // Right is ignored if there is no connection
+        if principal.ptype == PrincipalType::Pure {
+            occurrences(&principal.right, &mut used);
+        }
+    }
+
+    // Group occurrences by name
+    let mut left: HashMap<String, Vec<Span>> = HashMap::new();
+    let mut right: HashMap<String, Vec<Span>> = HashMap::new();
+    for (name, span) in &bound {
+        left.entry(name.clone()).or_default().push(*span);
+    }
+    for (name, span) in &used {
+        right.entry(name.clone()).or_default().push(*span);
+    }
+
+    // Check every variable once in order of first appearance
+    let mut seen: Vec<&String> = vec![];
+    for (name, _) in bound.iter().chain(used.iter()) {
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+
+        let l = left.get(name).cloned().unwrap_or_default();
+        let r = right.get(name).cloned().unwrap_or_default();
+
+        if l.len() > 1 {
+            violations.push(Violation::Repeated { name: name.clone(), spans: l.clone() });
+        }
+        // Variable not bound by the active pair is a wire inside the result and needs two ends
+        let allowed = if l.is_empty() { 2 } else { 1 };
+        if r.len() > allowed {
+            violations.push(Violation::Repeated { name: name.clone(), spans: r.clone() });
+        }
+        if !l.is_empty() && r.is_empty() {
+            violations.push(Violation::Unused { name: name.clone(), span: l[0] });
+        }
+        if l.is_empty() && r.len() == 1 {
+            violations.push(Violation::Unbound { name: name.clone(), span: r[0] });
+        }
+    }
+
+    // Report in order of appearance
+    violations.sort_by_key(|v| v.span().start);
+
+    violations
+}
+
+// Variables of the term are either interface ports (once) or wires (twice)
+pub fn check_term(terms: &[Semantic::Principal]) -> Vec<Violation> {
+    let mut found = vec![];
+    for term in terms {
+        occurrences(&term.left, &mut found);
+        if term.ptype == PrincipalType::Pure {
+            occurrences(&term.right, &mut found);
+        }
+    }
+    let mut violations = vec![];
+    let mut seen: Vec<&String> = vec![];
+    for (name, _) in &found {
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+        let spans: Vec<Span> = found.iter().filter(|(n, _)| n == name).map(|(_, span)| *span).collect();
+        if spans.len() > 2 {
+            violations.push(Violation::Repeated { name: name.clone(), spans });
+        }
+    }
+    violations
+}
+
+// Determine number of auxiliary ports of every agent from declarations and usage
+pub fn infer_arities(program: &Semantic::Program) -> (HashMap<String, usize>, Vec<Violation>) {
+
+    let mut violations = vec![];
+    // Arity and where it was first fixed
+    let mut arities: HashMap<String, (usize, Span)> = HashMap::new();
+
+    // Declarations take precedence over usage
+    for signature in &program.signatures {
+        match arities.get(&signature.name) {
+            Some((arity, origin)) => {
+                if *arity != signature.arity {
+                    violations.push(Violation::Redeclared {
+                        name: signature.name.clone(),
+                        span: signature.span,
+                        origin: *origin,
+                    });
+                }
+            }
+            None => {
+                arities.insert(signature.name.clone(), (signature.arity, signature.span));
+            }
+        }
+    }
+
+    fn infer(agent: &Semantic::Agent,
+             arities: &mut HashMap<String, (usize, Span)>,
+             violations: &mut Vec<Violation>) {
+        if agent.atype != AgentType::Var {
+            let found = agent.ports.len();
+            match arities.get(&agent.name) {
+                Some((expected, origin)) => {
+                    if *expected != found {
+                        violations.push(Violation::Arity {
+                            name: agent.name.clone(),
+                            expected: *expected,
+                            found,
+                            span: agent.span,
+                            origin: *origin,
+                        });
+                    }
+                }
+                None => {
+                    arities.insert(agent.name.clone(), (found, agent.span));
+                }
+            }
+        }
+        for port in &agent.ports {
+            infer(port, arities, violations);
+        }
+    }
+
+    fn infer_principal(principal: &Semantic::Principal,
+                       arities: &mut HashMap<String, (usize, Span)>,
+                       violations: &mut Vec<Violation>) {
+        infer(&principal.left, arities, violations);
+        // Right is a placeholder if there is no connection
+        if principal.ptype == PrincipalType::Pure {
+            infer(&principal.right, arities, violations);
+        }
+    }
+
+    // Usage in order of appearance
+    for equation in &program.equations {
+        infer(&equation.left, &mut arities, &mut violations);
+        infer(&equation.right, &mut arities, &mut violations);
+        for principal in &equation.principals {
+            infer_principal(principal, &mut arities, &mut violations);
+        }
+    }
+    for term in &program.terms {
+        infer_principal(term, &mut arities, &mut violations);
+    }
+
+    let arities = arities.into_iter().map(|(name, (arity, _))| (name, arity)).collect();
+
+    (arities, violations)
+}
+
+// Same equation with left and right agent of active pair swapped
+pub fn mirror(equation: &Semantic::Equation) -> Semantic::Equation {
+    Semantic::Equation {
+        left: equation.right.clone(),
+        right: equation.left.clone(),
+        principals: equation.principals.clone(),
+        span: equation.span,
+    }
+}
+
+// Compare agents up to consistent renaming of variables
+fn equivalent_agents(a: &Semantic::Agent, b: &Semantic::Agent, names: &mut HashMap<String, String>) -> bool {
+    if a.atype != b.atype || a.ports.len() != b.ports.len() {
+        return false;
+    }
+    if a.atype == AgentType::Var {
+        // Renaming has to be a bijection
+        return match names.get(&a.name) {
+            Some(n) => *n == b.name,
+            None => {
+                if names.values().any(|n| *n == b.name) {
+                    return false;
+                }
+                names.insert(a.name.clone(), b.name.clone());
+                true
+            }
+        };
+    }
+    a.name == b.name && a.ports.iter().zip(b.ports.iter()).all(|(p, q)| equivalent_agents(p, q, names))
+}
+
+// Compare connections, both sides of a connection may be swapped
+fn equivalent_principals(a: &Semantic::Principal, b: &Semantic::Principal, names: &mut HashMap<String, String>) -> bool {
+    if a.ptype != b.ptype {
+        return false;
+    }
+    if a.ptype != PrincipalType::Pure {
+        return equivalent_agents(&a.left, &b.left, names);
+    }
+    // Try as written first
+    let mut straight = names.clone();
+    if equivalent_agents(&a.left, &b.left, &mut straight) && equivalent_agents(&a.right, &b.right, &mut straight) {
+        *names = straight;
+        return true;
+    }
+    let mut swapped = names.clone();
+    if equivalent_agents(&a.left, &b.right, &mut swapped) && equivalent_agents(&a.right, &b.left, &mut swapped) {
+        *names = swapped;
+        return true;
+    }
+    false
+}
+
+// Two equations describe the same rule if they only differ in variable names
+pub fn equivalent(a: &Semantic::Equation, b: &Semantic::Equation) -> bool {
+    let mut names = HashMap::new();
+    equivalent_agents(&a.left, &b.left, &mut names)
+        && equivalent_agents(&a.right, &b.right, &mut names)
+        && a.principals.len() == b.principals.len()
+        && a.principals.iter().zip(b.principals.iter()).all(|(p, q)| equivalent_principals(p, q, &mut names))
+}
+
+// Every active pair may only have one rule, regardless of the order of its agents
+pub fn check_conflicts(program: &Semantic::Program) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (i, equation) in program.equations.iter().enumerate() {
+        for earlier in &program.equations[..i] {
+            // Orient earlier rule like this one
+            let earlier = if earlier.left.name == equation.left.name && earlier.right.name == equation.right.name {
+                earlier.clone()
+            } else if earlier.left.name == equation.right.name && earlier.right.name == equation.left.name {
+                mirror(earlier)
+            } else {
+                continue;
+            };
+            // Rules for agents of the same type are also equal when mirrored
+            let same = equivalent(equation, &earlier)
+                || (equation.left.name == equation.right.name && equivalent(equation, &mirror(&earlier)));
+            if !same {
+                violations.push(Violation::Conflict {
+                    left: equation.left.name.clone(),
+                    right: equation.right.name.clone(),
+                    span: equation.span,
+                    origin: earlier.span,
+                });
+                break;
+            }
+        }
+    }
+    violations
+}
+
+// Check all equations of program
+pub fn check(program: &Semantic::Program) -> Vec<Violation> {
+    let mut violations = vec![];
+    for equation in &program.equations {
+        violations.append(&mut check_equation(equation));
+    }
+    violations.append(&mut check_term(&program.terms));
+    violations.append(&mut infer_arities(program).1);
+    violations.append(&mut check_conflicts(program));
+    // Report in order of appearance
+    violations.sort_by_key(|v| v.span().start);
+    violations
+}
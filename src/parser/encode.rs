@@ -0,0 +1,540 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::interaction::{InteractionNet, Port, FREE};
+use crate::interaction::combinators::*;
+use crate::interaction::vm::VM;
+use crate::parser::*;
+use crate::parser::builtin::with_builtins;
+use crate::parser::check::{check, infer_arities, mirror};
+use crate::parser::native::find_native;
+use crate::parser::transform::{agent_types, INTERFACE};
+
+// Every agent A(x1, ..., xn) of the program becomes the same small net of combinators. Behind its
+// principal port it offers a menu holding a reference to the rule of A for every other agent type,
+// a selector picking the rule for A out of the menu of the other agent and the bundled ports x1, ..., xn.
+// When two encoded agents meet, each one expands the rule it picked and receives its own ports
+// together with two channels to the rule picked by the other one and a wire shared with it.
+// For different agents the one with the lower type builds the result and the other one passes its
+// ports over, agents of the same type build one half of the result each.
+// Not every program can be encoded: neither agent of a pair of the same type knows which side it is on,
+// so a rule between them is rejected with CompileError::Asymmetric unless its result is the mirror
+// image of itself. References to the rules are expanded as a seventh rewrite, see combinators.rs.
+
+// Types and arities of an encoded program, the menu of every agent has a rule for each type
+#[derive(Debug, Clone)]
+pub struct Encoding {
+    pub type_mapping: HashMap<String, u64>,
+    pub arities: Vec<usize>, // Number of auxiliary ports of every agent type
+}
+
+// Where one end of a wire of a result or the initial net is attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Site {
+    Left(usize),       // Auxiliary port of left agent of active pair
+    Right(usize),      // Auxiliary port of right agent of active pair
+    Agent(usize, u64), // Port of n-th new agent
+}
+
+// Occurrence of a variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum End {
+    Site(Site),
+    Side(usize, usize), // Side of a connection between two variables (connection, side)
+    Free,               // Variable is not connected to anything
+}
+
+// Agents of a result or the initial net and the wires between them
+#[derive(Debug, Default)]
+struct Graph {
+    agents: Vec<(u64, usize)>,       // Type and arity of every agent
+    wires: BTreeMap<Site, Site>,     // Other end of every wire, in both directions
+    boundary: Vec<(String, Site)>,   // Variables occurring only once
+    ends: HashMap<String, Vec<End>>, // Occurrences of every variable
+    order: Vec<String>,              // Variables in order of first occurrence
+}
+
+impl Graph {
+
+    // Remember occurrence of variable
+    fn occur(&mut self, name: &str, end: End) {
+        if !self.ends.contains_key(name) {
+            self.order.push(name.to_string());
+        }
+        self.ends.entry(name.to_string()).or_default().push(end);
+    }
+
+    fn connect(&mut self, site0: Site, site1: Site) {
+        self.wires.insert(site0, site1);
+        self.wires.insert(site1, site0);
+    }
+
+    // Add agent with its sub agents and return its index
+    fn agent(&mut self, agent: &Semantic::Agent, encoding: &Encoding) -> usize {
+        let atype = encoding.type_mapping[&agent.name];
+        let index = self.agents.len();
+        self.agents.push((atype, encoding.arities[atype as usize]));
+        for (i, port) in agent.ports.iter().enumerate() {
+            let site = Site::Agent(index, i as u64 + 1);
+            if port.atype == AgentType::Var {
+                self.occur(&port.name, End::Site(site));
+            } else {
+                let child = self.agent(port, encoding);
+                self.connect(Site::Agent(child, 0), site);
+            }
+        }
+        index
+    }
+
+    // Add n-th connection
+    fn principal(&mut self, n: usize, principal: &Semantic::Principal, encoding: &Encoding) {
+        match principal.ptype {
+            PrincipalType::Pure => {
+                let (left, right) = (&principal.left, &principal.right);
+                match (left.atype == AgentType::Var, right.atype == AgentType::Var) {
+                    (false, false) => {
+                        let (l, r) = (self.agent(left, encoding), self.agent(right, encoding));
+                        self.connect(Site::Agent(l, 0), Site::Agent(r, 0));
+                    }
+                    (true, false) => {
+                        let r = self.agent(right, encoding);
+                        self.occur(&left.name, End::Site(Site::Agent(r, 0)));
+                    }
+                    (false, true) => {
+                        let l = self.agent(left, encoding);
+                        self.occur(&right.name, End::Site(Site::Agent(l, 0)));
+                    }
+                    (true, true) => {
+                        self.occur(&left.name, End::Side(n, 0));
+                        self.occur(&right.name, End::Side(n, 1));
+                    }
+                }
+            }
+            PrincipalType::Var => self.occur(&principal.left.name, End::Free),
+            PrincipalType::Cons => {
+                // Constant is not connected to anything
+                self.agent(&principal.left, encoding);
+            }
+        }
+    }
+
+    // Follow wire of variable from one end to the other, crossing connections between variables
+    fn follow(&self, name: &str, from: End) -> Option<End> {
+        let mut name = name.to_string();
+        let mut at = from;
+        loop {
+            let next = *self.ends[&name].iter().find(|e| **e != at)?;
+            match next {
+                End::Side(n, side) => {
+                    at = End::Side(n, 1 - side);
+                    name = self.order.iter()
+                        .find(|var| self.ends[*var].contains(&at))
+                        .unwrap()
+                        .clone();
+                }
+                end => return Some(end),
+            }
+        }
+    }
+
+    // Turn variables into wires, variables with a single end become the boundary
+    fn resolve(mut self) -> Self {
+        for name in self.order.clone() {
+            for end in self.ends[&name].clone() {
+                let End::Site(site) = end else { continue };
+                match self.follow(&name, end) {
+                    Some(End::Site(other)) => self.connect(site, other),
+                    Some(_) => {}
+                    None => self.boundary.push((name.clone(), site)),
+                }
+            }
+        }
+        self
+    }
+
+    // Result of equation, left agent of the active pair is the left side of the equation
+    fn rule(equation: &Semantic::Equation, encoding: &Encoding) -> Self {
+        let mut graph = Graph::default();
+        for (i, port) in equation.left.ports.iter().enumerate() {
+            graph.occur(&port.name, End::Site(Site::Left(i)));
+        }
+        for (i, port) in equation.right.ports.iter().enumerate() {
+            graph.occur(&port.name, End::Site(Site::Right(i)));
+        }
+        for (n, principal) in equation.principals.iter().enumerate() {
+            graph.principal(n, principal, encoding);
+        }
+        graph.resolve()
+    }
+
+    // Initial net
+    fn term(terms: &[Semantic::Principal], encoding: &Encoding) -> Self {
+        let mut graph = Graph::default();
+        for (n, term) in terms.iter().enumerate() {
+            graph.principal(n, term, encoding);
+        }
+        graph.resolve()
+    }
+
+    // Swap of the agents of the active pair as a mapping of new agents,
+    // None if it does not map the result onto itself or keeps an agent in place
+    fn symmetry(&self, arity: usize) -> Option<Vec<usize>> {
+        let mut sigma: Vec<Option<usize>> = vec![None; self.agents.len()];
+        let other = |site: Site| self.wires.get(&site).copied();
+        let mut queue: Vec<_> = (0..arity).map(|i| (other(Site::Left(i)), other(Site::Right(i)))).collect();
+        while let Some(ends) = queue.pop() {
+            match ends {
+                (None, None) => {}
+                (Some(Site::Left(i)), Some(Site::Right(j))) | (Some(Site::Right(i)), Some(Site::Left(j))) if i == j => {}
+                (Some(Site::Agent(x, p)), Some(Site::Agent(y, q))) if p == q && self.agents[x] == self.agents[y] => {
+                    match (sigma[x], sigma[y]) {
+                        (Some(z), _) if z == y => continue,
+                        (None, None) => {}
+                        _ => return None,
+                    }
+                    sigma[x] = Some(y);
+                    sigma[y] = Some(x);
+                    for port in 0..=self.agents[x].1 as u64 {
+                        queue.push((other(Site::Agent(x, port)), other(Site::Agent(y, port))));
+                    }
+                }
+                _ => return None,
+            }
+        }
+        // Every agent needs a mirror image on the other half
+        sigma.iter().enumerate().map(|(x, y)| y.filter(|y| *y != x)).collect()
+    }
+}
+
+// Builds nets of combinators
+struct Encoder<'a> {
+    encoding: &'a Encoding,
+    menus: &'a [Vec<u64>], // Type of reference to rule of agent type for every other agent type
+}
+
+impl<'a> Encoder<'a> {
+
+    // Tree of n - 1 agents bundling n wires at target, returns ends of the wires.
+    // Mirrored trees take constructor trees apart, duplicator trees meet one another unmirrored
+    fn tree(t: &mut Template, atype: u64, target: Port, n: usize, mirrored: bool) -> Vec<Port> {
+        match n {
+            0 => {
+                let era = t.node(ERA);
+                t.connect((era, 0), target);
+                vec![]
+            }
+            1 => vec![target],
+            _ => {
+                let node = t.node(atype);
+                t.connect((node, 0), target);
+                let (leaf, rest) = if mirrored { (2, 1) } else { (1, 2) };
+                let mut ends = vec![(node, leaf)];
+                ends.extend(Encoder::tree(t, atype, (node, rest), n - 1, mirrored));
+                ends
+            }
+        }
+    }
+
+    // Encoded agent, returns its principal port and the ends of its auxiliary ports
+    fn agent(&self, t: &mut Template, atype: u64) -> (Port, Vec<Port>) {
+        let k = self.menus.len();
+        let top = t.node(DUP);
+        let pair = t.node(CON);
+        t.connect((top, 1), (pair, 0));
+        // Menu of rules
+        let menu = t.node(CON);
+        t.connect((pair, 1), (menu, 0));
+        for (end, reference) in Encoder::tree(t, CON, (menu, 2), k, false).into_iter().zip(&self.menus[atype as usize]) {
+            let node = t.node(*reference);
+            t.connect((node, 0), end);
+        }
+        // Selector erasing every rule but the one for this type
+        let select = t.node(CON);
+        t.connect((pair, 2), (select, 0));
+        let picked = t.node(CON);
+        for (j, end) in Encoder::tree(t, CON, (select, 1), k, true).into_iter().enumerate() {
+            if j as u64 == atype {
+                t.connect((picked, 0), end);
+            } else {
+                let era = t.node(ERA);
+                t.connect((era, 0), end);
+            }
+        }
+        // Picked rule of the other agent receives arguments and channels
+        t.connect((menu, 1), (picked, 1));
+        let channel = t.node(CON);
+        t.connect((select, 2), (channel, 0));
+        t.connect((picked, 2), (channel, 2));
+        let args = t.node(CON);
+        t.connect((channel, 1), (args, 0));
+        t.connect((args, 2), (top, 2));
+        let ends = Encoder::tree(t, CON, (args, 1), self.encoding.arities[atype as usize], false);
+        ((top, 0), ends)
+    }
+
+    // Encoded agents of graph for which keep holds, returns ports of their sites
+    fn agents(&self, t: &mut Template, graph: &Graph, keep: impl Fn(usize) -> bool) -> HashMap<Site, Port> {
+        let mut sites = HashMap::new();
+        for (x, (atype, _)) in graph.agents.iter().enumerate() {
+            if !keep(x) {
+                continue;
+            }
+            let (principal, ends) = self.agent(t, *atype);
+            sites.insert(Site::Agent(x, 0), principal);
+            for (i, end) in ends.into_iter().enumerate() {
+                sites.insert(Site::Agent(x, i as u64 + 1), end);
+            }
+        }
+        sites
+    }
+
+    // Code of rule when agent of type own meets agent of type other,
+    // left side of equation is the agent of type own
+    fn rule(&self, equation: &Semantic::Equation, own: u64, other: u64) -> Result<Template, CompileError> {
+        let mut t = Template::new();
+        // Take channels, own arguments and the wire shared with the other rule apart
+        let first = t.node(CON);
+        t.connect((first, 0), FREE);
+        let outer = (first, 1);
+        let second = t.node(CON);
+        t.connect((first, 2), (second, 0));
+        let inner = (second, 1);
+        let third = t.node(CON);
+        t.connect((second, 2), (third, 0));
+        let shared = (third, 1);
+        let args = (third, 2);
+
+        let erase = |t: &mut Template, port: Port| {
+            let era = t.node(ERA);
+            t.connect((era, 0), port);
+        };
+
+        // Pass arguments over to the other rule
+        if own > other {
+            t.connect(args, inner);
+            erase(&mut t, outer);
+            erase(&mut t, shared);
+            return Ok(t);
+        }
+
+        let graph = Graph::rule(equation, self.encoding);
+        let arity = self.encoding.arities[own as usize];
+        let mut sites = HashMap::new();
+        for (i, end) in Encoder::tree(&mut t, CON, args, arity, true).into_iter().enumerate() {
+            sites.insert(Site::Left(i), end);
+        }
+
+        // Build whole result
+        if own < other {
+            for (i, end) in Encoder::tree(&mut t, CON, outer, self.encoding.arities[other as usize], true).into_iter().enumerate() {
+                sites.insert(Site::Right(i), end);
+            }
+            erase(&mut t, inner);
+            erase(&mut t, shared);
+            sites.extend(self.agents(&mut t, &graph, |_| true));
+            for (site0, site1) in &graph.wires {
+                if site0 < site1 {
+                    t.connect(sites[site0], sites[site1]);
+                }
+            }
+            return Ok(t);
+        }
+
+        // Build half of the result, the other half is its mirror image
+        let sigma = graph.symmetry(arity).ok_or(CompileError::Asymmetric { span: equation.span })?;
+        let image = |site: Site| match site {
+            Site::Left(i) => Site::Right(i),
+            Site::Right(i) => Site::Left(i),
+            Site::Agent(x, p) => Site::Agent(sigma[x], p),
+        };
+        let mine = |site: Site| match site {
+            Site::Left(_) => true,
+            Site::Right(_) => false,
+            Site::Agent(x, _) => x < sigma[x],
+        };
+        sites.extend(self.agents(&mut t, &graph, |x| x < sigma[x]));
+        // Wires between the halves, each one is sent over inner and received over outer
+        let mut crossing = vec![];
+        // Wires between a port and its own mirror image
+        let mut fixed = vec![];
+        for (site0, site1) in &graph.wires {
+            match (mine(*site0), mine(*site1)) {
+                (true, true) if site0 < site1 => t.connect(sites[site0], sites[site1]),
+                (true, false) => {
+                    let other = image(*site1);
+                    if other == *site0 {
+                        fixed.push(*site0);
+                    } else if !crossing.contains(&(other, *site0)) {
+                        crossing.push((*site0, other));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let sent = Encoder::tree(&mut t, CON, inner, crossing.len(), false);
+        let received = Encoder::tree(&mut t, CON, outer, crossing.len(), true);
+        for (i, (site0, site1)) in crossing.iter().enumerate() {
+            t.connect(sites[site0], sent[i]);
+            t.connect(sites[site1], received[i]);
+        }
+        for (site, end) in fixed.iter().zip(Encoder::tree(&mut t, DUP, shared, fixed.len(), false)) {
+            t.connect(sites[site], end);
+        }
+        Ok(t)
+    }
+}
+
+// Compile program into a net of interaction combinators, every rule becomes a definition of the book
+pub fn compile_combinators(program: Semantic::Program) -> Result<(Combinators, Encoding), CompileError> {
+
+    // Values of numbers do not fit into combinators
+    if let Some(span) = find_native(&program) {
+        return Err(CompileError::Native { span });
+    }
+
+    // Built-in agents are ordinary agents of the encoded program
+    let program = with_builtins(&program);
+
+    let violations = check(&program);
+    if !violations.is_empty() {
+        return Err(CompileError::Invalid(violations));
+    }
+
+    let mut type_mapping = agent_types(&program);
+    let inferred = infer_arities(&program).0;
+    let mut arities = vec![0; type_mapping.len()];
+    for (name, atype) in &type_mapping {
+        arities[*atype as usize] = inferred.get(name).copied().unwrap_or(0);
+    }
+    let mut encoding = Encoding { type_mapping: type_mapping.clone(), arities };
+    let k = encoding.arities.len();
+
+    // Rule for every ordered pair of types, left side of the equation is the first type
+    let mut rules: BTreeMap<(u64, u64), Semantic::Equation> = BTreeMap::new();
+    for equation in &program.equations {
+        let types = (type_mapping[&equation.left.name], type_mapping[&equation.right.name]);
+        // Check made sure that repeated rules are equivalent
+        if rules.contains_key(&types) {
+            continue;
+        }
+        rules.insert(types, equation.clone());
+        rules.insert((types.1, types.0), mirror(equation));
+    }
+
+    // Definition 0 stands for pairs without rule, references to it get stuck
+    let mut cn = Combinators::new();
+    let none = cn.define(None);
+    let mut menus = vec![vec![none; k]; k];
+    for (n, types) in rules.keys().enumerate() {
+        menus[types.0 as usize][types.1 as usize] = REF + 1 + n as u64;
+    }
+
+    let encoder = Encoder { encoding: &encoding, menus: &menus };
+    for ((own, other), equation) in &rules {
+        let template = encoder.rule(equation, *own, *other)?;
+        cn.define(Some(template));
+    }
+
+    // Initial net, variables occurring once are connected to boundary agents
+    let graph = Graph::term(&program.terms, &encoding);
+    let mut t = Template::new();
+    let sites = encoder.agents(&mut t, &graph, |_| true);
+    for (site0, site1) in &graph.wires {
+        if site0 < site1 {
+            t.connect(sites[site0], sites[site1]);
+        }
+    }
+    let mut boundary = vec![];
+    for (name, site) in &graph.boundary {
+        let node = t.node(VAR);
+        t.connect((node, 1), sites[site]);
+        boundary.push((name.clone(), node));
+    }
+    let ids = cn.instantiate(&t, FREE);
+    for (name, node) in boundary {
+        cn.interface.insert(name, ids[node as usize - 1]);
+    }
+    if !graph.boundary.is_empty() {
+        type_mapping.insert(INTERFACE.to_string(), k as u64);
+    }
+    encoding.type_mapping = type_mapping;
+
+    Ok((cn, encoding))
+}
+
+// Encoded agent whose top is agent id, returns its type and the ends of its auxiliary ports
+fn recognize(net: &InteractionNet, id: u64, encoding: &Encoding) -> Option<(u64, Vec<Port>)> {
+    // Agent of given type facing port with its principal port
+    let at = |port: Port, atype: u64| {
+        let end = net.port(port);
+        (end != FREE && end.1 == 0 && net.atype(end.0) == atype).then_some(end.0)
+    };
+    // Ends of the wires bundled at port
+    fn tree(port: Port, n: usize, mirrored: bool, at: &dyn Fn(Port, u64) -> Option<u64>) -> Option<Vec<Port>> {
+        match n {
+            0 => at(port, ERA).map(|_| vec![]),
+            1 => Some(vec![port]),
+            _ => {
+                let node = at(port, CON)?;
+                let (leaf, rest) = if mirrored { (2, 1) } else { (1, 2) };
+                let mut ends = vec![(node, leaf)];
+                ends.extend(tree((node, rest), n - 1, mirrored, at)?);
+                Some(ends)
+            }
+        }
+    }
+
+    if net.atype(id) != DUP {
+        return None;
+    }
+    let pair = at((id, 1), CON)?;
+    at((pair, 1), CON)?;
+    let select = at((pair, 2), CON)?;
+    // Selected port tells the type
+    let k = encoding.arities.len();
+    let picks = tree((select, 1), k, true, &at)?;
+    let atype = picks.iter().position(|end| at(*end, CON).is_some())?;
+    let channel = at((select, 2), CON)?;
+    let args = at((channel, 1), CON)?;
+    if net.port((args, 2)) != (id, 2) {
+        return None;
+    }
+    let ends = tree((args, 1), encoding.arities[atype], false, &at)?;
+    Some((atype as u64, ends))
+}
+
+// Net of the agents of the program the combinators stand for, in a vm without rules.
+// Parts of the net which are no encoded agents are left out
+pub fn decode(cn: &Combinators, encoding: &Encoding) -> VM {
+    let net = &cn.interaction_net;
+    let mut vm = VM::new();
+    // Port of decoded net every port of the combinators stands for
+    let mut ports: BTreeMap<Port, Port> = BTreeMap::new();
+    for agent in net.heap.values() {
+        if let Some((atype, ends)) = recognize(net, agent.id, encoding) {
+            let id = vm.interaction_net.gen_id();
+            vm.interaction_net.new_agent(id, atype, ends.len() as u64);
+            ports.insert((agent.id, 0), (id, 0));
+            for (i, end) in ends.into_iter().enumerate() {
+                ports.insert(end, (id, i as u64 + 1));
+            }
+        }
+    }
+    // Boundary agents
+    if let Some(interface) = encoding.type_mapping.get(INTERFACE) {
+        for (name, var) in &cn.interface {
+            let id = vm.interaction_net.gen_id();
+            vm.interaction_net.new_agent(id, *interface, 1);
+            ports.insert((*var, 1), (id, 1));
+            vm.interface.insert(name.clone(), id);
+        }
+    }
+    for (from, to) in &ports {
+        let end = net.port(*from);
+        if let Some(other) = ports.get(&end) {
+            // Every wire once
+            if from < &end {
+                vm.interaction_net.bind_agents(*to, *other);
+            }
+        }
+    }
+    vm
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
+use crate::parser::Rule;
+use crate::parser::check::Violation;
+use crate::interaction::verify::VerifyError;
+
+// Location of a piece of source text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,   // Byte offset of first character
+    pub end: usize,     // Byte offset behind last character
+    pub line: usize,    // Line of first character (starting at 1)
+    pub col: usize,     // Column of first character (starting at 1)
+}
+
+impl Span {
+
+    pub fn from_pest(span: &pest::Span) -> Self {
+        let (line, col) = span.start_pos().line_col();
+        Span {
+            start: span.start(),
+            end: span.end(),
+            line,
+            col,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // Text does not match the grammar
+    Syntax {
+        span: Span,
+        expected: Vec<String>,
+        unexpected: Vec<String>,
+        snippet: String,
+    },
+    // Tree does not have the shape of a program
+    Malformed {
+        message: String,
+        span: Span,
+    },
+}
+
+impl ParseError {
+
+    pub fn from_pest(error: pest::error::Error<Rule>, text: &str) -> Self {
+        // Byte offsets
+        let (start, end) = match error.location {
+            InputLocation::Pos(p) => (p, p),
+            InputLocation::Span(s) => s,
+        };
+        // Line and column
+        let (line, col) = match error.line_col {
+            LineColLocation::Pos(p) => p,
+            LineColLocation::Span(p, _) => p,
+        };
+        let span = Span { start, end, line, col };
+        // Rules pest was looking for
+        let (expected, unexpected) = match error.variant {
+            ErrorVariant::ParsingError { positives, negatives } => {
+                (positives.into_iter().map(describe).collect(),
+                 negatives.into_iter().map(describe).collect())
+            }
+            ErrorVariant::CustomError { message } => {
+                (vec![], vec![message])
+            }
+        };
+        ParseError::Syntax {
+            span,
+            expected,
+            unexpected,
+            snippet: snippet(text, span),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Syntax { span, expected, unexpected, snippet } => {
+                write!(f, "syntax error at {}:{}", span.line, span.col)?;
+                if !expected.is_empty() {
+                    write!(f, ", expected {}", expected.join(", "))?;
+                }
+                if !unexpected.is_empty() {
+                    write!(f, ", unexpected {}", unexpected.join(", "))?;
+                }
+                write!(f, "\n{}", snippet)
+            }
+            ParseError::Malformed { message, span } => {
+                write!(f, "malformed program at {}:{}: {}", span.line, span.col, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    // Rule needs more ports stored than the scratchpad can hold
+    ScratchpadExhausted {
+        span: Span,
+    },
+    // Program failed the semantic checks
+    Invalid(Vec<Violation>),
+    // Rule between agents of the same type can not be split into two mirrored halves
+    Asymmetric {
+        span: Span,
+    },
+    // Numbers and operators are computed by the vm and have no counterpart in other backends
+    Native {
+        span: Span,
+    },
+    // Generated code does not pass the verifier, which is a bug of the compiler
+    Bytecode {
+        error: VerifyError,
+        span: Span,
+    },
+}
+
+impl CompileError {
+
+    // Location of the offending source text
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::ScratchpadExhausted { span } => *span,
+            CompileError::Asymmetric { span } => *span,
+            CompileError::Native { span } => *span,
+            CompileError::Bytecode { span, .. } => *span,
+            CompileError::Invalid(violations) => {
+                violations.first().map(|v| v.span()).unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Every violation carries its own location
+        if let CompileError::Invalid(violations) = self {
+            write!(f, "program has {} error(s)", violations.len())?;
+            for v in violations {
+                write!(f, "\n{}", v)?;
+            }
+            return Ok(());
+        }
+        let span = self.span();
+        write!(f, "error at {}:{}: ", span.line, span.col)?;
+        match self {
+            CompileError::ScratchpadExhausted { .. } => {
+                write!(f, "too many wires to connect, scratchpad is exhausted")
+            }
+            CompileError::Asymmetric { .. } => {
+                write!(f, "rule does not look the same from both agents, it can not be encoded as combinators")
+            }
+            CompileError::Native { .. } => {
+                write!(f, "numbers and operators can not be encoded as combinators")
+            }
+            CompileError::Bytecode { error, .. } => {
+                write!(f, "compiled code is broken: {}", error)
+            }
+            CompileError::Invalid(_) => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// Human readable name of a grammar rule
+fn describe(rule: Rule) -> String {
+    match rule {
+        Rule::id => "agent name".to_string(),
+        Rule::var => "variable".to_string(),
+        Rule::cons => "constant".to_string(),
+        Rule::num => "number".to_string(),
+        Rule::op => "operator".to_string(),
+        Rule::agent => "agent".to_string(),
+        Rule::principal => "connection".to_string(),
+        Rule::equation => "equation".to_string(),
+        Rule::signature => "signature".to_string(),
+        Rule::term => "term".to_string(),
+        Rule::arity => "arity".to_string(),
+        Rule::abstraction => "abstraction".to_string(),
+        Rule::binding => "let binding".to_string(),
+        Rule::application => "application".to_string(),
+        Rule::lambda => "lambda term".to_string(),
+        Rule::EOI => "end of input".to_string(),
+        _ => format!("{:?}", rule),
+    }
+}
+
+// Render the line containing span with a caret underneath
+pub fn snippet(text: &str, span: Span) -> String {
+    // Line of text the span starts in
+    let line = text.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+    // Underline at least one character but never beyond the end of the line
+    let rest = line.chars().count().saturating_sub(span.col.saturating_sub(1));
+    let width = text.get(span.start..span.end)
+        .map(|s| s.chars().take_while(|c| *c != '\n').count())
+        .unwrap_or(0)
+        .min(rest)
+        .max(1);
+    // Gutter is as wide as the line number
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    format!("{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            number, line,
+            gutter, " ".repeat(span.col.saturating_sub(1)), "^".repeat(width))
+}
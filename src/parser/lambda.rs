@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use pest::Parser;
+use pest::iterators::Pair;
+use crate::interaction::{Port, FREE};
+use crate::interaction::vm::VM;
+use crate::parser::*;
+use crate::parser::builtin::{agent, annihilate, commute, connect, var, ERA};
+use crate::parser::readback::letters;
+use crate::parser::transform::{compileProgram, INTERFACE};
+
+// Lambda terms are translated into nets of abstractions, applications, duplicators and erasers.
+// Every variable used more than once is shared through duplicators with labels of their own, duplicators
+// with the same label annihilate and others commute. Without brackets this is the abstract algorithm of
+// optimal reduction, which is exact for terms typeable in elementary affine logic (e.g. church numerals).
+// Other terms may end up in nets that do not read back as terms, e.g. a numeral applied to its own copy
+
+// Agent names of the translation
+pub const LAM: &str = "LAM";  // LAM(body, var), principal port is the abstraction itself
+pub const APP: &str = "APP";  // APP(argument, result), principal port faces the function
+pub const DUP: &str = "DUP";  // DUP followed by label
+// Variable of the interface the whole term is connected to, no lambda variable contains a digit
+pub const ROOT: &str = "root0";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Var(String),
+    Lam(String, Box<Term>),
+    App(Box<Term>, Box<Term>),
+}
+
+// Parse lambda term, let x = t in u is read as (\x. u) t
+pub fn lambda(text: &str) -> Result<Term, ParseError> {
+
+    let mut parser = LangParser::parse(Rule::lambda, text)
+        .map_err(|e| ParseError::from_pest(e, text))?;
+
+    fn malformed(message: &str, span: Span) -> ParseError {
+        ParseError::Malformed { message: message.to_string(), span }
+    }
+
+    fn value(rule: &Pair<Rule>) -> Result<Term, ParseError> {
+        let span = Span::from_pest(&rule.as_span());
+        let mut it = rule.clone().into_inner();
+        let mut next = |what: &str| it.next().ok_or_else(|| malformed(&format!("expected {}", what), span));
+        match rule.as_rule() {
+            Rule::var => Ok(Term::Var(rule.as_str().to_string())),
+            Rule::abstraction => {
+                let name = next("variable")?.as_str().to_string();
+                let body = value(&next("body")?)?;
+                Ok(Term::Lam(name, Box::new(body)))
+            }
+            Rule::binding => {
+                let name = next("variable")?.as_str().to_string();
+                let bound = value(&next("bound term")?)?;
+                let body = value(&next("body")?)?;
+                Ok(Term::App(Box::new(Term::Lam(name, Box::new(body))), Box::new(bound)))
+            }
+            Rule::application => {
+                // Applications associate to the left
+                let mut term = value(&next("function")?)?;
+                for argument in it {
+                    term = Term::App(Box::new(term), Box::new(value(&argument)?));
+                }
+                Ok(term)
+            }
+            Rule::lambda => value(&next("term")?),
+            r => Err(malformed(&format!("unexpected {:?}", r), span)),
+        }
+    }
+
+    value(&parser.next().ok_or_else(|| malformed("empty input", Span::default()))?)
+}
+
+impl fmt::Display for Term {
+
+    // Same notation as in source text, e.g. \a. a (\b. b)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Var(name) => write!(f, "{}", name),
+            Term::Lam(name, body) => write!(f, "\\{}. {}", name, body),
+            Term::App(function, argument) => {
+                match **function {
+                    Term::Lam(_, _) => write!(f, "({})", function)?,
+                    _ => write!(f, "{}", function)?,
+                }
+                match **argument {
+                    Term::Var(_) => write!(f, " {}", argument),
+                    _ => write!(f, " ({})", argument),
+                }
+            }
+        }
+    }
+}
+
+// Builds connections of the net of a term
+struct Translator {
+    wires: usize,                          // Number of wire names handed out
+    labels: usize,                         // Number of duplicator labels handed out
+    terms: Vec<Semantic::Principal>,       // Connections of the net
+    scopes: Vec<(String, Vec<String>)>,    // Bound variables, innermost last, with the wires of their occurrences
+    free: Vec<(String, Vec<String>)>,      // Free variables with the wires of their occurrences
+}
+
+impl Translator {
+
+    // Fresh wire, names with digits do not clash with variables of the term
+    fn wire(&mut self) -> String {
+        self.wires += 1;
+        format!("w{}", self.wires)
+    }
+
+    // Connect occurrences of a variable to its binder
+    fn share(&mut self, uses: &[String]) -> Semantic::Agent {
+        match uses {
+            [] => agent(ERA, vec![]),
+            [single] => var(single.clone()),
+            [first, rest @ ..] => {
+                let label = self.labels;
+                self.labels += 1;
+                let rest = self.share(rest);
+                agent(&format!("{}{}", DUP, label), vec![var(first.clone()), rest])
+            }
+        }
+    }
+
+    // Net of term, returns what its result is connected to
+    fn translate(&mut self, term: &Term) -> Semantic::Agent {
+        match term {
+            Term::Var(name) => {
+                let wire = self.wire();
+                match self.scopes.iter_mut().rev().find(|(bound, _)| bound == name) {
+                    Some((_, uses)) => uses.push(wire.clone()),
+                    None => match self.free.iter_mut().find(|(free, _)| free == name) {
+                        Some((_, uses)) => uses.push(wire.clone()),
+                        None => self.free.push((name.clone(), vec![wire.clone()])),
+                    },
+                }
+                var(wire)
+            }
+            Term::Lam(name, body) => {
+                self.scopes.push((name.clone(), vec![]));
+                let body = self.translate(body);
+                let (_, uses) = self.scopes.pop().unwrap();
+                let binder = self.share(&uses);
+                agent(LAM, vec![body, binder])
+            }
+            Term::App(function, argument) => {
+                let function = self.translate(function);
+                let argument = self.translate(argument);
+                let result = self.wire();
+                self.terms.push(connect(agent(APP, vec![argument, var(result.clone())]), function));
+                var(result)
+            }
+        }
+    }
+}
+
+// Program reducing term, free variables of the term are variables of the interface
+pub fn translate(term: &Term) -> Semantic::Program {
+    let mut translator = Translator { wires: 0, labels: 0, terms: vec![], scopes: vec![], free: vec![] };
+    let root = translator.translate(term);
+    translator.terms.push(connect(var(ROOT.to_string()), root));
+    for (name, uses) in translator.free.clone() {
+        let binder = translator.share(&uses);
+        translator.terms.push(connect(var(name), binder));
+    }
+
+    // APP(a, r) # LAM(b, x) = a ~ x, r ~ b
+    let mut equations = vec![Semantic::Equation {
+        left: agent(APP, vec![var("a".to_string()), var("r".to_string())]),
+        right: agent(LAM, vec![var("b".to_string()), var("x".to_string())]),
+        principals: vec![
+            connect(var("a".to_string()), var("x".to_string())),
+            connect(var("r".to_string()), var("b".to_string())),
+        ],
+        span: Span::default(),
+    }];
+    // Duplicators copy everything but duplicators with the same label
+    for label in 0..translator.labels {
+        let dup = format!("{}{}", DUP, label);
+        equations.push(annihilate(&dup));
+        equations.push(commute(&dup, LAM, 2));
+        equations.push(commute(&dup, APP, 2));
+        for other in label + 1..translator.labels {
+            equations.push(commute(&dup, &format!("{}{}", DUP, other), 2));
+        }
+    }
+
+    Semantic::Program { signatures: vec![], equations, terms: translator.terms }
+}
+
+// Agents and wires of the connections of a term while they are created in a net
+#[derive(Default)]
+struct Net {
+    ports: HashMap<String, Vec<Port>>, // Ports every variable is attached to
+    links: Vec<(String, String)>,      // Connections between two variables
+    order: Vec<String>,                // Variables in order of first occurrence
+}
+
+impl Net {
+
+    fn occur(&mut self, name: &str) {
+        if !self.ports.contains_key(name) {
+            self.order.push(name.to_string());
+            self.ports.insert(name.to_string(), vec![]);
+        }
+    }
+
+    // Create agent with everything nested into it, returns its id
+    fn agent(&mut self, vm: &mut VM, type_mapping: &HashMap<String, u64>, agent: &Semantic::Agent) -> u64 {
+        let mut root = 0;
+        // Agents still to create with the port their principal port gets connected to
+        let mut work = vec![(agent, FREE)];
+        while let Some((agent, parent)) = work.pop() {
+            let net = &mut vm.interaction_net;
+            let id = net.gen_id();
+            net.new_agent(id, type_mapping[&agent.name], agent.ports.len() as u64);
+            if parent == FREE {
+                root = id;
+            } else {
+                net.bind_agents((id, 0), parent);
+            }
+            for (index, port) in agent.ports.iter().enumerate() {
+                let here = (id, index as u64 + 1);
+                if port.atype == AgentType::Var {
+                    self.occur(&port.name);
+                    self.ports.get_mut(&port.name).unwrap().push(here);
+                } else {
+                    work.push((port, here));
+                }
+            }
+        }
+        root
+    }
+
+    // Attach variable to principal port of agent
+    fn principal(&mut self, name: &str, id: u64) {
+        self.occur(name);
+        self.ports.get_mut(name).unwrap().push((id, 0));
+    }
+
+    // Connect ends of all wires, variables occurring once get a boundary agent of type interface.
+    // Returns boundary agent of every such variable
+    fn wire(mut self, vm: &mut VM, interface: u64) -> HashMap<String, u64> {
+        // Variables connected to each other stand for the same wire
+        let mut wire: HashMap<String, String> = HashMap::new();
+        let find = |wire: &HashMap<String, String>, name: &str| {
+            let mut name = name.to_string();
+            while let Some(next) = wire.get(&name) {
+                name = next.clone();
+            }
+            name
+        };
+        let mut uses: HashMap<String, usize> = self.ports.iter().map(|(name, ports)| (name.clone(), ports.len())).collect();
+        for (a, b) in &self.links {
+            *uses.get_mut(a).unwrap() += 1;
+            *uses.get_mut(b).unwrap() += 1;
+            let (a, b) = (find(&wire, a), find(&wire, b));
+            if a != b {
+                wire.insert(a, b);
+            }
+        }
+        let mut boundary = HashMap::new();
+        // Ends of every wire, in order of its first variable
+        let mut first = HashMap::new();
+        let mut ends: Vec<Vec<Port>> = vec![];
+        for name in &self.order {
+            let net = &mut vm.interaction_net;
+            if uses[name] == 1 {
+                let id = net.gen_id();
+                net.new_agent(id, interface, 1);
+                self.ports.get_mut(name).unwrap().push((id, 1));
+                boundary.insert(name.clone(), id);
+            }
+            let n = *first.entry(find(&wire, name)).or_insert_with(|| {
+                ends.push(vec![]);
+                ends.len() - 1
+            });
+            ends[n].extend(&self.ports[name]);
+        }
+        for ports in &ends {
+            if let [port0, port1] = ports[..] {
+                vm.interaction_net.bind_agents(port0, port1);
+            }
+        }
+        boundary
+    }
+}
+
+// Vm with the rules of the translation of term and its net, the net is created directly instead of
+// by initial code, so the size of the term is not bound by the scratchpad
+pub fn compile_lambda(term: &Term) -> Result<(VM, HashMap<String, u64>), CompileError> {
+    let program = translate(term);
+    // Erasers may only occur in the term, so they are declared for their rules to be generated
+    let rules = Semantic::Program {
+        signatures: vec![Semantic::Signature { name: ERA.to_string(), arity: 0, span: Span::default() }],
+        equations: program.equations,
+        terms: vec![],
+    };
+    let (mut vm, mut type_mapping) = compileProgram(rules)?;
+
+    let mut net = Net::default();
+    for term in &program.terms {
+        let (left, right) = (&term.left, &term.right);
+        match (left.atype == AgentType::Var, right.atype == AgentType::Var) {
+            (false, false) => {
+                let (l, r) = (net.agent(&mut vm, &type_mapping, left), net.agent(&mut vm, &type_mapping, right));
+                vm.interaction_net.bind_agents((l, 0), (r, 0));
+            }
+            (true, false) => {
+                let r = net.agent(&mut vm, &type_mapping, right);
+                net.principal(&left.name, r);
+            }
+            (false, true) => {
+                let l = net.agent(&mut vm, &type_mapping, left);
+                net.principal(&right.name, l);
+            }
+            (true, true) => {
+                net.occur(&left.name);
+                net.occur(&right.name);
+                net.links.push((left.name.clone(), right.name.clone()));
+            }
+        }
+    }
+    let interface = type_mapping.len() as u64;
+    vm.interface = net.wire(&mut vm, interface);
+    type_mapping.insert(INTERFACE.to_string(), interface);
+    Ok((vm, type_mapping))
+}
+
+// Step of reading a term back
+enum Task {
+    Wire(Port),                            // Read term at the other end of the wire connected to port
+    Leave((Port, BTreeMap<u64, Vec<u64>>)), // Wire has been read with these paths, it may be reached again
+    Lam(String),                           // Wrap body into abstraction of variable
+    Argument(u64),                         // Function of application is read, its argument comes next
+    App,                                   // Function and argument of application are read
+    Push(u64, u64),                        // Restore path of duplicator type after leaving it
+    Pop(u64),                              // Forget port duplicator type was entered from
+}
+
+// Reads terms back, duplicators in the net are passed through the same port they were entered from
+struct Reader<'a> {
+    vm: &'a VM,
+    names: HashMap<u64, String>,   // Name of every agent type
+    binders: HashMap<u64, String>, // Variable of every abstraction read so far
+    free: HashMap<u64, String>,    // Variable of every boundary agent
+    paths: BTreeMap<u64, Vec<u64>>, // Ports duplicators of every type were entered from
+    trail: HashSet<(Port, BTreeMap<u64, Vec<u64>>)>, // Ports being read with the paths they were reached by
+    count: usize,                  // Number of names handed out
+}
+
+impl<'a> Reader<'a> {
+
+    // Fresh variable name skipping free variables
+    fn fresh(&mut self) -> String {
+        loop {
+            let name = letters(self.count);
+            self.count += 1;
+            if !self.free.values().any(|free| *free == name) {
+                return name;
+            }
+        }
+    }
+
+    // Term at the other end of the wire connected to port, None if reading it runs in circles.
+    // Work is kept on a stack of its own, so deeply nested terms do not overflow the call stack
+    fn wire(&mut self, port: Port) -> Option<Term> {
+        let mut tasks = vec![Task::Wire(port)];
+        let mut terms = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Wire(port) => {
+                    let end = self.vm.interaction_net.port(port);
+                    if end == FREE {
+                        terms.push(Term::Var("_".to_string()));
+                        continue;
+                    }
+                    let visit = (end, self.paths.clone());
+                    if !self.trail.insert(visit.clone()) {
+                        return None;
+                    }
+                    tasks.push(Task::Leave(visit));
+                    self.follow(end, &mut tasks, &mut terms)?;
+                }
+                Task::Leave(visit) => {
+                    self.trail.remove(&visit);
+                }
+                Task::Lam(var) => {
+                    let body = terms.pop()?;
+                    terms.push(Term::Lam(var, Box::new(body)));
+                }
+                Task::Argument(id) => {
+                    tasks.push(Task::App);
+                    tasks.push(Task::Wire((id, 1)));
+                }
+                Task::App => {
+                    let argument = terms.pop()?;
+                    let function = terms.pop()?;
+                    terms.push(Term::App(Box::new(function), Box::new(argument)));
+                }
+                Task::Push(atype, index) => self.paths.entry(atype).or_default().push(index),
+                Task::Pop(atype) => {
+                    self.paths.entry(atype).or_default().pop();
+                }
+            }
+        }
+        terms.pop()
+    }
+
+    // Plan reading the term starting at end, None if it runs in circles
+    fn follow(&mut self, end: Port, tasks: &mut Vec<Task>, terms: &mut Vec<Term>) -> Option<()> {
+        let atype = self.vm.interaction_net.atype(end.0);
+        let name = self.names.get(&atype).cloned().unwrap_or_default();
+        match (name.as_str(), end.1) {
+            (LAM, 0) => {
+                let var = self.fresh();
+                self.binders.insert(end.0, var.clone());
+                tasks.push(Task::Lam(var));
+                tasks.push(Task::Wire((end.0, 1)));
+            }
+            (LAM, _) => terms.push(Term::Var(self.binders.get(&end.0).cloned().unwrap_or_else(|| "_".to_string()))),
+            (APP, _) => {
+                tasks.push(Task::Argument(end.0));
+                tasks.push(Task::Wire((end.0, 0)));
+            }
+            (INTERFACE, _) => terms.push(Term::Var(self.free.get(&end.0).cloned().unwrap_or_default())),
+            (dup, 0) if dup.starts_with(DUP) => {
+                // Leave through the port the duplicator was entered from last
+                let index = self.paths.entry(atype).or_default().pop().unwrap_or(1);
+                tasks.push(Task::Push(atype, index));
+                tasks.push(Task::Wire((end.0, index)));
+            }
+            (dup, index) if dup.starts_with(DUP) => {
+                // Entering more duplicators than the net ever had agents means running in circles
+                let path = self.paths.entry(atype).or_default();
+                if path.len() as u64 >= self.vm.interaction_net.cnt {
+                    return None;
+                }
+                path.push(index);
+                tasks.push(Task::Pop(atype));
+                tasks.push(Task::Wire((end.0, 0)));
+            }
+            _ => terms.push(Term::Var("*".to_string())),
+        }
+        Some(())
+    }
+}
+
+// Read reduced net of a lambda term back as term, None if it is no term
+pub fn readback_lambda(vm: &VM, type_mapping: &HashMap<String, u64>) -> Option<Term> {
+    let root = *vm.interface.get(ROOT)?;
+    let mut reader = Reader {
+        vm,
+        names: type_mapping.iter().map(|(name, atype)| (*atype, name.clone())).collect(),
+        binders: HashMap::new(),
+        free: vm.interface.iter().filter(|(name, _)| *name != ROOT).map(|(name, id)| (*id, name.clone())).collect(),
+        paths: BTreeMap::new(),
+        trail: HashSet::new(),
+        count: 0,
+    };
+    reader.wire((root, 1))
+}
@@ -1,11 +1,20 @@
 pub mod transform;
+pub mod error;
+pub mod check;
+pub mod readback;
+pub mod builtin;
+pub mod encode;
+pub mod lambda;
+pub mod native;
 
 use std::collections::VecDeque;
-use std::os::unix::io::AsFd;
+use std::fmt;
 use pest::Parser;
 use pest::iterators::Pair;
-use crate::parser::Semantic::{AgentType, Principal, PrincipalType, Program};
-use crate::parser::Semantic::AgentType::Agent;
+use crate::parser::Semantic::{AgentType, PrincipalType};
+
+pub use crate::parser::error::{CompileError, ParseError, Span};
+use crate::parser::native::NUM;
 
 #[derive(pest_derive::Parser)]
 #[grammar = "parser/lang.pest"]
@@ -13,91 +22,150 @@ pub struct LangParser;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AstNode {
-    Id(String),
-    Var(String),
-    Cons(String),
-    Agent(Box<AstNode>, Vec<AstNode>),
-    Principal(Box<AstNode>, Box<AstNode>),
-    Equation(Box<AstNode>, Box<AstNode>, Vec<AstNode>),
-    Program(Vec<AstNode>, Box<AstNode>),
+    Id(String, Span),
+    Var(String, Span),
+    Cons(String, Span),
+    Num(u64, Span),
+    Agent(Box<AstNode>, Vec<AstNode>, Span),
+    Principal(Box<AstNode>, Box<AstNode>, Span),
+    Equation(Box<AstNode>, Box<AstNode>, Vec<AstNode>, Span),
+    Signature(Box<AstNode>, usize, Span),
+    Term(Vec<AstNode>, Span),
+    Program(Vec<AstNode>, Box<AstNode>, Span),
 }
 
-pub fn ast(text: &String) -> AstNode {
+impl AstNode {
+
+    // Source text the node was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            AstNode::Id(_, span) => *span,
+            AstNode::Var(_, span) => *span,
+            AstNode::Cons(_, span) => *span,
+            AstNode::Num(_, span) => *span,
+            AstNode::Agent(_, _, span) => *span,
+            AstNode::Principal(_, _, span) => *span,
+            AstNode::Equation(_, _, _, span) => *span,
+            AstNode::Signature(_, _, span) => *span,
+            AstNode::Term(_, span) => *span,
+            AstNode::Program(_, _, span) => *span,
+        }
+    }
+}
 
-    let parser = LangParser::parse(Rule::program, text).unwrap();
+pub fn ast(text: &str) -> Result<AstNode, ParseError> {
 
-    fn value(rule: &Pair<Rule>) -> AstNode {
-        return match rule.as_rule() {
-            Rule::id => {
-                AstNode::Id(String::from(rule.as_str()))
+    let mut parser = LangParser::parse(Rule::program, text)
+        .map_err(|e| ParseError::from_pest(e, text))?;
+
+    fn malformed(message: &str, span: Span) -> ParseError {
+        ParseError::Malformed { message: message.to_string(), span }
+    }
+
+    fn value(rule: &Pair<Rule>) -> Result<AstNode, ParseError> {
+        // Location of rule in source text
+        let span = Span::from_pest(&rule.as_span());
+        match rule.as_rule() {
+            Rule::id | Rule::op => {
+                Ok(AstNode::Id(String::from(rule.as_str()), span))
             }
             Rule::var => {
-                AstNode::Var(String::from(rule.as_str()))
+                Ok(AstNode::Var(String::from(rule.as_str()), span))
             }
             Rule::cons => {
-                AstNode::Cons(String::from(rule.as_str()))
+                Ok(AstNode::Cons(String::from(rule.as_str()), span))
+            }
+            Rule::num => {
+                let value = rule.as_str().parse::<u64>()
+                    .map_err(|_| malformed("number out of range", span))?;
+                Ok(AstNode::Num(value, span))
             }
             Rule::agent => {
                 // Iterator
-                let mut it = rule.clone().into_inner().into_iter();
+                let mut it = rule.clone().into_inner();
                 // Id is first node
-                let id = value(&it.next().unwrap());
+                let id = value(&it.next().ok_or_else(|| malformed("agent without name", span))?)?;
                 // Get rest of nodes
                 let mut v = vec![];
                 for i in it {
-                    v.push(value(&i));
+                    v.push(value(&i)?);
                 }
                 // Return
-                AstNode::Agent(Box::new(id), v)
+                Ok(AstNode::Agent(Box::new(id), v, span))
             }
             Rule::principal => {
                 // Iterator
-                let mut it = rule.clone().into_inner().into_iter();
-                AstNode::Principal(Box::new(value(&it.next().unwrap())),
-                                   Box::new(value(&it.next().unwrap())))
+                let mut it = rule.clone().into_inner();
+                let left = value(&it.next().ok_or_else(|| malformed("connection without left side", span))?)?;
+                let right = value(&it.next().ok_or_else(|| malformed("connection without right side", span))?)?;
+                Ok(AstNode::Principal(Box::new(left), Box::new(right), span))
             }
             Rule::equation => {
-                let mut it = rule.clone().into_inner().into_iter();
+                let mut it = rule.clone().into_inner();
                 // Left side of equation
-                let mut left = Box::new(value(&it.next().unwrap()));
+                let left = Box::new(value(&it.next().ok_or_else(|| malformed("equation without left agent", span))?)?);
                 // Right side of equation
-                let mut right = Box::new(value(&it.next().unwrap()));
+                let right = Box::new(value(&it.next().ok_or_else(|| malformed("equation without right agent", span))?)?);
                 // Connections
                 let mut v = vec![];
                 for i in it {
-                    v.push(value(&i));
+                    v.push(value(&i)?);
                 }
-                AstNode::Equation(left, right, v)
+                Ok(AstNode::Equation(left, right, v, span))
+            }
+            Rule::signature => {
+                let mut it = rule.clone().into_inner();
+                // Name of agent
+                let id = value(&it.next().ok_or_else(|| malformed("signature without name", span))?)?;
+                // Number of auxiliary ports
+                let arity = it.next().ok_or_else(|| malformed("signature without arity", span))?;
+                let arity = arity.as_str().parse::<usize>()
+                    .map_err(|_| malformed("arity out of range", Span::from_pest(&arity.as_span())))?;
+                Ok(AstNode::Signature(Box::new(id), arity, span))
+            }
+            Rule::term => {
+                // Connections
+                let mut v = vec![];
+                for i in rule.clone().into_inner() {
+                    v.push(value(&i)?);
+                }
+                Ok(AstNode::Term(v, span))
             }
             Rule::program => {
                 // Iterator
-                let mut it = rule.clone().into_inner().into_iter();
+                let it = rule.clone().into_inner();
                 // Queue
                 let mut vd = VecDeque::new();
                 // Collect elements
                 for i in it {
-                    vd.push_back(value(&i));
+                    // End of input marker carries no information
+                    if i.as_rule() != Rule::EOI {
+                        vd.push_back(value(&i)?);
+                    }
                 }
                 // Last one is term that should be evaluated
-                let term = vd.pop_back().unwrap();
-                AstNode::Program(vd.try_into().unwrap(), Box::new(term))
+                let term = vd.pop_back().ok_or_else(|| malformed("program without term", span))?;
+                Ok(AstNode::Program(vd.into(), Box::new(term), span))
             }
-            _ => {
-                panic!("AST generation failed")
+            r => {
+                Err(malformed(&format!("unexpected {:?}", r), span))
             }
-        };
+        }
     }
 
-    return value(&parser.into_iter().next().unwrap());
+    value(&parser.next().ok_or_else(|| malformed("empty input", Span::default()))?)
 }
 
 pub mod Semantic {
 
+    use crate::parser::Span;
+
     #[derive(Debug, Clone, PartialEq)]
     pub enum AgentType {
         Var,
         Cons,
         Agent,
+        Num(u64),   // Number literal, its name is the one of all numbers
     }
 
     #[derive(Debug, Clone, PartialEq)]
@@ -112,6 +180,7 @@ pub mod Semantic {
         pub name: String,
         pub atype: AgentType,
         pub ports: Vec<Agent>,
+        pub span: Span,
     }
 
     #[derive(Debug, Clone)]
@@ -119,6 +188,7 @@ pub mod Semantic {
         pub ptype: PrincipalType,
         pub left: Agent,
         pub right: Agent,
+        pub span: Span,
     }
 
     #[derive(Debug, Clone)]
@@ -126,149 +196,258 @@ pub mod Semantic {
         pub left: Agent,
         pub right: Agent,
         pub principals: Vec<Principal>,
+        pub span: Span,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Signature {
+        pub name: String,
+        pub arity: usize,
+        pub span: Span,
     }
 
     #[derive(Debug, Clone)]
     pub struct Program {
+        pub signatures: Vec<Signature>,
         pub equations: Vec<Equation>,
-        pub term: Principal,
+        pub terms: Vec<Principal>,  // Connections of the initial net
     }
 }
 
 impl Semantic::Program {
 
-    pub fn fromAst(ast: AstNode) -> Self {
+    pub fn fromAst(ast: AstNode) -> Result<Self, ParseError> {
+
+        fn illegal(what: &str, ast: &AstNode) -> ParseError {
+            ParseError::Malformed { message: format!("expected {}, found {:?}", what, ast), span: ast.span() }
+        }
 
-        fn scanAgent(ast: AstNode) -> Semantic::Agent {
+        fn scanAgent(ast: AstNode) -> Result<Semantic::Agent, ParseError> {
             match ast {
-                AstNode::Var(s) => {
-                    Semantic::Agent{
+                AstNode::Var(s, span) => {
+                    Ok(Semantic::Agent{
                         name: s.clone(),
                         atype: AgentType::Var,
                         ports: vec![],
-                    }
+                        span,
+                    })
                 }
-                AstNode::Cons(s) => {
-                    Semantic::Agent{
+                AstNode::Cons(s, span) => {
+                    Ok(Semantic::Agent{
                         name: s.clone(),
                         atype: AgentType::Cons,
                         ports: vec![],
-                    }
+                        span,
+                    })
+                }
+                AstNode::Num(value, span) => {
+                    Ok(Semantic::Agent{
+                        name: NUM.to_string(),
+                        atype: AgentType::Num(value),
+                        ports: vec![],
+                        span,
+                    })
                 }
-                AstNode::Agent(id, ports) => {
+                AstNode::Agent(id, ports, span) => {
                     // Extract name
                     let s = match *id {
-                        AstNode::Id(s) => {
+                        AstNode::Id(s, _) => {
                             s
                         }
-                        _ => {"".to_string()}
+                        other => {
+                            return Err(illegal("agent name", &other));
+                        }
                     };
                     // Scan sub agents
                     let mut pv = vec![];
                     for p in ports {
-                        pv.push(scanAgent(p.clone()));
+                        pv.push(scanAgent(p)?);
                     }
                     // Return
-                    Semantic::Agent{
+                    Ok(Semantic::Agent{
                         name: s.clone(),
                         atype: AgentType::Agent,
                         ports: pv,
-                    }
+                        span,
+                    })
                 },
-                _ => {
-                    panic!("Illegal argument");
+                other => {
+                    Err(illegal("agent", &other))
                 }
             }
         }
 
-        fn scanPrincipal(ast: AstNode) -> Semantic::Principal {
+        fn scanPrincipal(ast: AstNode) -> Result<Semantic::Principal, ParseError> {
             match ast {
-                AstNode::Principal(left, right) => {
-                    Semantic::Principal{
+                AstNode::Principal(left, right, span) => {
+                    Ok(Semantic::Principal{
                         ptype: PrincipalType::Pure,
-                        left: scanAgent(*left),
-                        right: scanAgent(*right),
-                    }
+                        left: scanAgent(*left)?,
+                        right: scanAgent(*right)?,
+                        span,
+                    })
                 }
-                AstNode::Var(s) => {
-                    Semantic::Principal{
+                AstNode::Var(s, span) => {
+                    Ok(Semantic::Principal{
                         ptype: PrincipalType::Var,
                         left: Semantic::Agent{
                             name: s.clone(),
                             atype: AgentType::Var,
                             ports: vec![],
+                            span,
                         },
                         // Right is ignored in this case
                         right: Semantic::Agent{
                             name: "".to_string(),
                             atype: AgentType::Agent,
                             ports: vec![],
-                        }
-                    }
+                            span,
+                        },
+                        span,
+                    })
                 }
-                AstNode::Cons(s) => {
-                    Semantic::Principal{
+                AstNode::Cons(s, span) => {
+                    Ok(Semantic::Principal{
                         ptype: PrincipalType::Cons,
                         left: Semantic::Agent{
                             name: s.clone(),
                             atype: AgentType::Cons,
                             ports: vec![],
+                            span,
                         },
                         // Right is ignored in this case
                         right: Semantic::Agent{
                             name: "".to_string(),
                             atype: AgentType::Agent,
                             ports: vec![],
-                        }
-                    }
+                            span,
+                        },
+                        span,
+                    })
+                }
+                AstNode::Num(_, span) => {
+                    Ok(Semantic::Principal{
+                        ptype: PrincipalType::Cons,
+                        left: scanAgent(ast)?,
+                        // Right is ignored in this case
+                        right: Semantic::Agent{
+                            name: "".to_string(),
+                            atype: AgentType::Agent,
+                            ports: vec![],
+                            span,
+                        },
+                        span,
+                    })
                 }
-                _ => {
-                    panic!("Illegal argument")
+                other => {
+                    Err(illegal("connection", &other))
                 }
             }
         }
 
-        fn scanEquation(ast: AstNode) -> Semantic::Equation {
+        fn scanEquation(ast: AstNode) -> Result<Semantic::Equation, ParseError> {
             match ast {
-                AstNode::Equation(left, right, principals) => {
+                AstNode::Equation(left, right, principals, span) => {
 
                     let mut pv = vec![];
                     for p in principals {
-                        pv.push(scanPrincipal(p));
+                        pv.push(scanPrincipal(p)?);
                     }
 
-                    Semantic::Equation{
-                        left: scanAgent(*left),
-                        right: scanAgent(*right),
+                    Ok(Semantic::Equation{
+                        left: scanAgent(*left)?,
+                        right: scanAgent(*right)?,
                         principals: pv,
+                        span,
+                    })
+                }
+                other => {
+                    Err(illegal("equation", &other))
+                }
+            }
+        }
+
+        fn scanSignature(id: AstNode, arity: usize, span: Span) -> Result<Semantic::Signature, ParseError> {
+            match id {
+                AstNode::Id(name, _) => {
+                    Ok(Semantic::Signature{
+                        name,
+                        arity,
+                        span,
+                    })
+                }
+                other => {
+                    Err(illegal("agent name", &other))
+                }
+            }
+        }
+
+        fn scanTerm(ast: AstNode) -> Result<Vec<Semantic::Principal>, ParseError> {
+            match ast {
+                AstNode::Term(principals, _) => {
+                    let mut pv = vec![];
+                    for p in principals {
+                        pv.push(scanPrincipal(p)?);
                     }
+                    Ok(pv)
                 }
-                _ => {
-                    panic!("Illegal argument")
+                other => {
+                    Err(illegal("term", &other))
                 }
             }
         }
 
-        fn scanProgram(ast: AstNode) -> Semantic::Program {
+        fn scanProgram(ast: AstNode) -> Result<Semantic::Program, ParseError> {
             match ast {
-                AstNode::Program(equations, term) => {
+                AstNode::Program(statements, term, _) => {
 
+                    let mut sv = vec![];
                     let mut ev = vec![];
-                    for e in equations {
-                        ev.push(scanEquation(e));
+                    for e in statements {
+                        match e {
+                            AstNode::Signature(id, arity, span) => {
+                                sv.push(scanSignature(*id, arity, span)?);
+                            }
+                            e => {
+                                ev.push(scanEquation(e)?);
+                            }
+                        }
                     }
 
-                    return Semantic::Program{
+                    Ok(Semantic::Program{
+                        signatures: sv,
                         equations: ev,
-                        term: scanPrincipal(*term),
-                    }
+                        terms: scanTerm(*term)?,
+                    })
                 },
-                _ => {
-                    panic!("Illegal argument")
+                other => {
+                    Err(illegal("program", &other))
                 }
-            };
+            }
         }
 
-        return scanProgram(ast);
+        scanProgram(ast)
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Semantic::Agent {
+
+    // Same notation as in source text, e.g. S(S(O))
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let AgentType::Num(value) = self.atype {
+            return write!(f, "{}", value);
+        }
+        write!(f, "{}", self.name)?;
+        if self.atype == AgentType::Agent {
+            write!(f, "(")?;
+            for (index, port) in self.ports.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", port)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use crate::interaction::vm::{Instruction, Operator, OPERATORS};
+use crate::parser::*;
+use crate::parser::check::infer_arities;
+
+// Name of all numbers, agent names are upper case so it can not clash with them
+pub const NUM: &str = "number";
+
+// Operator agents OP(y, r) are named after the symbol of the operator and compute r = x op y for
+// the number x at their principal port
+
+// Operator which got its first operand and waits for the second one
+pub fn partial(symbol: &str) -> String {
+    format!("{}_", symbol)
+}
+
+// Agents carrying a value, new agents of these types take it over from the active pair
+pub fn valued(name: &str) -> bool {
+    name == NUM || OPERATORS.iter().any(|operator| partial(operator.symbol()) == name)
+}
+
+// Number or operator anywhere in the program
+pub fn find_native(program: &Semantic::Program) -> Option<Span> {
+    fn find(agent: &Semantic::Agent) -> Option<Span> {
+        if agent.name == NUM || OPERATORS.iter().any(|operator| operator.symbol() == agent.name) {
+            return Some(agent.span);
+        }
+        agent.ports.iter().find_map(find)
+    }
+    let principals = program.equations.iter().flat_map(|eq| eq.principals.iter()).chain(program.terms.iter());
+    program.equations.iter().flat_map(|eq| [&eq.left, &eq.right])
+        .chain(principals.flat_map(|p| [&p.left, &p.right]))
+        .find_map(find)
+}
+
+// Add signatures of numbers and operators the program uses, so built-in agents get rules for them
+pub fn with_natives(program: &Semantic::Program) -> Semantic::Program {
+    let mut program = program.clone();
+    let arities = infer_arities(&program).0;
+
+    let mut declare = |name: String, arity: usize| {
+        if !program.signatures.iter().any(|signature| signature.name == name) {
+            program.signatures.push(Semantic::Signature { name, arity, span: Span::default() });
+        }
+    };
+    for operator in OPERATORS {
+        let symbol = operator.symbol();
+        if arities.contains_key(symbol) {
+            // Result of an operator is a number
+            declare(symbol.to_string(), 2);
+            declare(partial(symbol), 1);
+            declare(NUM.to_string(), 0);
+        }
+    }
+    if arities.contains_key(NUM) {
+        declare(NUM.to_string(), 0);
+    }
+    program
+}
+
+// OP(y, r) # x = OP_(r) ~ y, where OP_ holds x, operator and number are stored at addresses o and n
+fn first(o: u64, n: u64, waiting: u64) -> Vec<Instruction> {
+    vec![
+        // Active pair, left agent is on top of stack
+        Instruction::POP(0),
+        Instruction::POP(1),
+        // Partial operator takes over value of number
+        Instruction::GEN,
+        Instruction::DUP,
+        Instruction::CONST(waiting),
+        Instruction::CONST(1),
+        Instruction::NEW_AGENT,
+        Instruction::DUP,
+        Instruction::PUSH(n),
+        Instruction::LOAD,
+        Instruction::STORE,
+        // and the wires of both auxiliary ports of the operator
+        Instruction::DUP,
+        Instruction::CONST(0),
+        Instruction::PUSH(o),
+        Instruction::CONST(1),
+        Instruction::LINK,
+        Instruction::CONST(1),
+        Instruction::PUSH(o),
+        Instruction::CONST(2),
+        Instruction::LINK,
+        Instruction::PUSH(0),
+        Instruction::DROP_AGENT,
+        Instruction::PUSH(1),
+        Instruction::DROP_AGENT,
+    ]
+}
+
+// OP_(r) # y = r ~ z, where z = x op y for x held by OP_, which is stored at address p and y at n
+fn second(p: u64, n: u64, operator: Operator, number: u64) -> Vec<Instruction> {
+    vec![
+        // Active pair, left agent is on top of stack
+        Instruction::POP(0),
+        Instruction::POP(1),
+        // Result
+        Instruction::GEN,
+        Instruction::DUP,
+        Instruction::CONST(number),
+        Instruction::CONST(0),
+        Instruction::NEW_AGENT,
+        Instruction::DUP,
+        Instruction::PUSH(p),
+        Instruction::LOAD,
+        Instruction::PUSH(n),
+        Instruction::LOAD,
+        Instruction::OPERATE(operator),
+        Instruction::STORE,
+        // takes over wire of the result port
+        Instruction::CONST(0),
+        Instruction::PUSH(p),
+        Instruction::CONST(1),
+        Instruction::LINK,
+        Instruction::PUSH(0),
+        Instruction::DROP_AGENT,
+        Instruction::PUSH(1),
+        Instruction::DROP_AGENT,
+    ]
+}
+
+// Rules of all operators in type mapping, computed by the vm instead of rewriting
+pub fn native_rules(type_mapping: &HashMap<String, u64>) -> Vec<((u64, u64), Vec<Instruction>)> {
+    let mut rules = vec![];
+    let number = match type_mapping.get(NUM) {
+        Some(number) => *number,
+        None => return rules,
+    };
+    for operator in OPERATORS {
+        let symbol = operator.symbol();
+        let (Some(op), Some(waiting)) = (type_mapping.get(symbol), type_mapping.get(&partial(symbol))) else {
+            continue;
+        };
+        // Pair may become active either way round, left agent is stored at address 0
+        rules.push(((*op, number), first(0, 1, *waiting)));
+        rules.push(((number, *op), first(1, 0, *waiting)));
+        rules.push(((*waiting, number), second(0, 1, operator, number)));
+        rules.push(((number, *waiting), second(1, 0, operator, number)));
+    }
+    rules
+}
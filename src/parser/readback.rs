@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use crate::interaction::{InteractionNet, Port, FREE};
+use crate::interaction::vm::VM;
+use crate::parser::*;
+use crate::parser::native::NUM;
+
+// Agent whose auxiliary ports are being read
+struct Frame {
+    id: u64,
+    atype: u64,
+    value: u64,
+    arity: usize,
+    ports: Vec<Semantic::Agent>, // Terms of the ports read so far
+}
+
+// Walks the net and turns agents back into terms
+pub struct Reader<'a> {
+    net: &'a InteractionNet,
+    names: HashMap<u64, String>,         // Name of every agent type
+    visited: HashSet<u64>,               // Agents already part of a term
+    wires: HashMap<(Port, Port), String>, // Variable of every wire that can not be drawn as nesting
+    fixed: HashMap<Port, String>,         // Ports of boundary agents with the name of their variable
+    count: usize,                         // Number of names handed out
+}
+
+impl<'a> Reader<'a> {
+
+    pub fn new(net: &'a InteractionNet, type_mapping: &HashMap<String, u64>) -> Self {
+        Reader {
+            net,
+            names: type_mapping.iter().map(|(name, atype)| (*atype, name.clone())).collect(),
+            visited: HashSet::new(),
+            wires: HashMap::new(),
+            fixed: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    // Name wires to boundary agents after the variables of the initial term
+    pub fn with_interface(mut self, interface: &HashMap<String, u64>) -> Self {
+        for (var, id) in interface {
+            self.fixed.insert((*id, 1), var.clone());
+        }
+        self
+    }
+
+    // Fresh variable name skipping names of the interface
+    fn fresh(&mut self) -> String {
+        loop {
+            let name = letters(self.count);
+            self.count += 1;
+            if !self.fixed.values().any(|fixed| *fixed == name) {
+                return name;
+            }
+        }
+    }
+
+    // Variable standing for wire from port to endpoint, both ends get the same name
+    fn var(&mut self, port: Port, endpoint: Port) -> Semantic::Agent {
+        // Unconnected ports are distinct wires
+        let key = if endpoint == FREE {
+            (port, FREE)
+        } else {
+            (port.min(endpoint), port.max(endpoint))
+        };
+        let name = match self.wires.get(&key).or_else(|| self.fixed.get(&endpoint)) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self.fresh();
+                self.wires.insert(key, name.clone());
+                name
+            }
+        };
+        Semantic::Agent {
+            name,
+            atype: AgentType::Var,
+            ports: vec![],
+            span: Span::default(),
+        }
+    }
+
+    // Start reading agent, its ports are read afterwards
+    fn enter(&mut self, id: u64) -> Frame {
+        self.visited.insert(id);
+        let agent = self.net.agent(id);
+        Frame { id, atype: agent.atype, value: agent.value, arity: agent.ports.len() - 1, ports: vec![] }
+    }
+
+    // Term of agent whose ports are all read
+    fn leave(&self, frame: Frame) -> Semantic::Agent {
+        let name = self.names.get(&frame.atype).cloned().unwrap_or_else(|| format!("#{}", frame.atype));
+        // Numbers are written as their value, agents without auxiliary ports as constants
+        let atype = if name == NUM {
+            AgentType::Num(frame.value)
+        } else if frame.ports.is_empty() {
+            AgentType::Cons
+        } else {
+            AgentType::Agent
+        };
+        Semantic::Agent {
+            name,
+            atype,
+            ports: frame.ports,
+            span: Span::default(),
+        }
+    }
+
+    // Only agents facing us with their principal port can be nested, and each only once
+    fn nests(&self, endpoint: Port) -> bool {
+        endpoint != FREE && endpoint.1 == 0 && !self.visited.contains(&endpoint.0)
+    }
+
+    // Term for agent and everything hanging off its auxiliary ports.
+    // Agents being read are kept on a stack of their own, so long chains do not overflow the call stack
+    pub fn agent(&mut self, id: u64) -> Semantic::Agent {
+        let mut frames = vec![self.enter(id)];
+        loop {
+            let frame = frames.last().unwrap();
+            if frame.ports.len() < frame.arity {
+                // Read next auxiliary port
+                let port = (frame.id, frame.ports.len() as u64 + 1);
+                let endpoint = self.net.port(port);
+                if self.nests(endpoint) {
+                    frames.push(self.enter(endpoint.0));
+                } else {
+                    let var = self.var(port, endpoint);
+                    frames.last_mut().unwrap().ports.push(var);
+                }
+                continue;
+            }
+            let term = self.leave(frames.pop().unwrap());
+            match frames.last_mut() {
+                Some(parent) => parent.ports.push(term),
+                None => return term,
+            }
+        }
+    }
+
+    // Term at the other end of the wire connected to port
+    pub fn wire(&mut self, port: Port) -> Semantic::Agent {
+        let endpoint = self.net.port(port);
+        if self.nests(endpoint) {
+            self.agent(endpoint.0)
+        } else {
+            self.var(port, endpoint)
+        }
+    }
+}
+
+// n-th variable name in order a, b, ..., z, aa, ab, ...
+pub fn letters(mut n: usize) -> String {
+    let mut name = String::new();
+    loop {
+        name.insert(0, (b'a' + (n % 26) as u8) as char);
+        if n < 26 {
+            return name;
+        }
+        n = n / 26 - 1;
+    }
+}
+
+// Reconstruct term of agent and all agents reachable through its auxiliary ports
+pub fn readback(net: &InteractionNet, type_mapping: &HashMap<String, u64>, root: u64) -> Semantic::Agent {
+    Reader::new(net, type_mapping).agent(root)
+}
+
+// Reconstruct term connected to port
+pub fn readback_port(net: &InteractionNet, type_mapping: &HashMap<String, u64>, port: Port) -> Semantic::Agent {
+    Reader::new(net, type_mapping).wire(port)
+}
+
+// Reconstruct term connected to variable of the initial term, other variables keep their names
+pub fn readback_interface(vm: &VM, type_mapping: &HashMap<String, u64>, name: &str) -> Option<Semantic::Agent> {
+    let boundary = *vm.interface.get(name)?;
+    Some(Reader::new(&vm.interaction_net, type_mapping).with_interface(&vm.interface).wire((boundary, 1)))
+}
+
+// Agents whose principal port is not connected, every one of them is the root of a result
+pub fn roots(net: &InteractionNet) -> Vec<u64> {
+    net.heap.values().filter(|agent| agent.ports[0] == FREE).map(|agent| agent.id).collect()
+}
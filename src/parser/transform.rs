@@ -1,172 +1,387 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use crate::interaction::vm::{Instruction, VM};
+use crate::interaction::vm::{Instruction, SCRATCHPAD_SIZE, VM};
+use crate::interaction::verify::verify;
 use crate::parser::*;
+use crate::parser::check::{check, check_equation, infer_arities, mirror};
+use crate::parser::builtin::with_builtins;
+use crate::parser::native::{native_rules, valued, with_natives};
 
-// Builds agent and leaves id on stack
-fn build_agent(type_mapping: &HashMap<String, u64>,
-               variable_mapping: &HashMap<String, u64>,
-               agent: &Semantic::Agent) -> Vec<Instruction>
-{
-    let mut code = vec![];
+// Type name of boundary agents standing for the variables of the initial term,
+// agent names are upper case so it can not clash with them
+pub const INTERFACE: &str = "interface";
 
-    match agent.atype {
-        AgentType::Var => {
-            code.push(Instruction::PUSH(variable_mapping.get(&agent.name).unwrap().clone()));
+// Where one end of a wire of the result is attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum End {
+    Pair(u64, u64),     // Auxiliary port of active pair (scratchpad address of agent id, port index)
+    Slot(u64),          // Port of new agent, agent id and port index are stored at address and address + 1
+    Side(usize, usize), // Side of a connection between two variables (connection, side)
+    Agent(u64, u64),    // Port of agent of the initial net (agent id, port index)
+    Free,               // Variable is not connected to anything
+}
+
+// Generates code for the agents of a result and remembers where variables occur
+struct Builder<'a> {
+    type_mapping: &'a HashMap<String, u64>,
+    arities: &'a HashMap<String, usize>,
+    code: Vec<Instruction>,
+    slot: u64,                          // Next free scratchpad address
+    next: u64,                          // Id of next agent of the initial net
+    ends: HashMap<String, Vec<End>>,    // Occurrences of every variable
+    values: HashMap<String, u64>,       // Scratchpad address of agent of active pair carrying a value
+    sides: HashMap<End, String>,        // Variable at side of connection
+    order: Vec<(String, Span)>,         // Variables in order of first occurrence
+}
+
+impl<'a> Builder<'a> {
+
+    fn new(type_mapping: &'a HashMap<String, u64>, arities: &'a HashMap<String, usize>, slot: u64) -> Self {
+        Builder {
+            type_mapping,
+            arities,
+            code: vec![],
+            slot,
+            next: 0,
+            ends: HashMap::new(),
+            values: HashMap::new(),
+            sides: HashMap::new(),
+            order: vec![],
         }
-        AgentType::Cons => {
-            // Id
-            code.push(Instruction::GEN);
-            // Save id for later
-            code.push(Instruction::DUP);
-            // Type
-            code.push(Instruction::CONST(type_mapping.get(&agent.name).unwrap().clone()));
-            // Create agent
-            code.push(Instruction::NEW_AGENT);
+    }
+
+    // Remember occurrence of variable
+    fn occur(&mut self, var: &Semantic::Agent, end: End) {
+        if !self.ends.contains_key(&var.name) {
+            self.order.push((var.name.clone(), var.span));
         }
-        Agent => {
-            // Id
-            code.push(Instruction::GEN);
-            // Save id for later
-            code.push(Instruction::DUP);
-            // Type
-            code.push(Instruction::CONST(type_mapping.get(&agent.name).unwrap().clone()));
-            // Create agent
-            code.push(Instruction::NEW_AGENT);
-            // Connect and create children
-            for port in &agent.ports {
-                // Copy own id
-                code.push(Instruction::DUP);
-                // Create other agents
-                code.append(&mut build_agent(&type_mapping, &variable_mapping, &port));
-                // Connect auxiliary ports
-                code.push(Instruction::CONST(0));
-                code.push(Instruction::CONST(0));
-                code.push(Instruction::BIND);
-            }
+        self.ends.entry(var.name.clone()).or_default().push(end);
+        if let End::Side(_, _) = end {
+            self.sides.insert(end, var.name.clone());
         }
     }
 
-    return code;
-}
+    // Store port on stack in scratchpad and return its address
+    fn store(&mut self, span: Span) -> Result<u64, CompileError> {
+        let addr = self.slot;
+        if addr as usize + 1 >= SCRATCHPAD_SIZE {
+            return Err(CompileError::ScratchpadExhausted { span });
+        }
+        self.code.push(Instruction::POP(addr + 1));
+        self.code.push(Instruction::POP(addr));
+        self.slot = addr + 2;
+        Ok(addr)
+    }
 
-pub fn compileEquation(equation: &Semantic::Equation, type_mapping: &HashMap<String, u64>) -> Vec<Instruction> {
+    // Builds agent and leaves its principal port on stack
+    fn build(&mut self, agent: &Semantic::Agent) -> Result<(), CompileError> {
+        // Id
+        self.code.push(Instruction::GEN);
+        // Save id for later
+        self.code.push(Instruction::DUP);
+        // Type
+        self.code.push(Instruction::CONST(*self.type_mapping.get(&agent.name).unwrap()));
+        // Number of auxiliary ports
+        let arity = self.arities.get(&agent.name).copied().unwrap_or(agent.ports.len());
+        self.code.push(Instruction::CONST(arity as u64));
+        // Create agent
+        self.code.push(Instruction::NEW_AGENT);
+        // Numbers carry their value, copies take it over from the active pair
+        if let AgentType::Num(value) = agent.atype {
+            self.code.push(Instruction::DUP);
+            self.code.push(Instruction::CONST(value));
+            self.code.push(Instruction::STORE);
+        } else if let Some(addr) = self.values.get(&agent.name) {
+            self.code.push(Instruction::DUP);
+            self.code.push(Instruction::PUSH(*addr));
+            self.code.push(Instruction::LOAD);
+            self.code.push(Instruction::STORE);
+        }
+        // Connect and create children
+        for (index, port) in agent.ports.iter().enumerate() {
+            // Auxiliary port of this agent
+            self.code.push(Instruction::DUP);
+            self.code.push(Instruction::CONST(index as u64 + 1));
+            if port.atype == AgentType::Var {
+                // Wire gets connected when all agents exist
+                let addr = self.store(port.span)?;
+                self.occur(port, End::Slot(addr));
+            } else {
+                // Connect principal port of child to auxiliary port
+                self.build(port)?;
+                self.code.push(Instruction::BIND);
+            }
+        }
+        // Principal port
+        self.code.push(Instruction::CONST(0));
+        Ok(())
+    }
 
-    // Code
-    let mut code : Vec<Instruction> = Vec::new();
-    // Map var name to postion of id in memory
-    let mut variable_mapping: HashMap<String, u64> = HashMap::new();
-
-    // 1. get agent id and port for every var on left side of equation
-    // Since on the left side only depth 1 agents are allowed we can determine them easily
-    // Then save var ids on heap for easy access
-
-    // Index
-    let mut index: u64 = 0;
-
-    // Process agent
-    let lports = equation.left.ports.clone();
-    // Skip principal port
-    for port in 0..lports.len() {
-        if lports[port].atype == AgentType::Var {
-            if !(variable_mapping.contains_key(&lports[port].name.clone())) {
-                // Save variable mapping
-                let heap_pos = variable_mapping.len() as u64 + 1;
-                variable_mapping.insert(lports[port].name.clone(), heap_pos);
-                // Duplicate agent id on stack
-                code.push(Instruction::DUP);
-                // Push port index (but skip principal port)
-                code.push(Instruction::CONST(index + 1));
-                // Get id of connected agent
-                code.push(Instruction::PORT);
-                // Store id on heap
-                code.push(Instruction::POP(heap_pos));
+    // Creates agent of the initial net with everything nested into it and returns its id. Initial code
+    // runs on an empty net, so agents get their ids in order of creation and are addressed by them
+    fn place(&mut self, agent: &Semantic::Agent) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.code.push(Instruction::GEN);
+        self.code.push(Instruction::CONST(*self.type_mapping.get(&agent.name).unwrap()));
+        let arity = self.arities.get(&agent.name).copied().unwrap_or(agent.ports.len());
+        self.code.push(Instruction::CONST(arity as u64));
+        self.code.push(Instruction::NEW_AGENT);
+        if let AgentType::Num(value) = agent.atype {
+            self.code.push(Instruction::CONST(id));
+            self.code.push(Instruction::CONST(value));
+            self.code.push(Instruction::STORE);
+        }
+        for (index, port) in agent.ports.iter().enumerate() {
+            if port.atype == AgentType::Var {
+                self.occur(port, End::Agent(id, index as u64 + 1));
+            } else {
+                // Connect principal port of child to auxiliary port
+                let child = self.place(port);
+                self.push_end(End::Agent(id, index as u64 + 1));
+                self.push_end(End::Agent(child, 0));
+                self.code.push(Instruction::BIND);
             }
         }
-        // Increment index
-        index = index + 1;
+        id
     }
 
-    // Store id of second agent on heap position 0
-    code.push(Instruction::POP(0));
-
-    // Reset index
-    index = 0;
-
-    // Process agent
-    let rports = equation.right.ports.clone();
-    // Skip principal port
-    for port in 0..rports.len() {
-        if rports[port].atype == AgentType::Var {
-            if !(variable_mapping.contains_key(&rports[port].name.clone())) {
-                // Save variable mapping
-                let heap_pos = variable_mapping.len() as u64 + 1;
-                variable_mapping.insert(rports[port].name.clone(), heap_pos);
-                // Duplicate agent id on stack
-                code.push(Instruction::DUP);
-                // Push port index (but skip principal port)
-                code.push(Instruction::CONST(index + 1));
-                // Get id of connected agent
-                code.push(Instruction::PORT);
-                // Store id on heap
-                code.push(Instruction::POP(heap_pos));
+    // Builds both sides of n-th connection of the initial net
+    fn term(&mut self, n: usize, principal: &Semantic::Principal) {
+        match principal.ptype {
+            PrincipalType::Pure => {
+                let (left, right) = (&principal.left, &principal.right);
+                match (left.atype == AgentType::Var, right.atype == AgentType::Var) {
+                    (false, false) => {
+                        let (l, r) = (self.place(left), self.place(right));
+                        self.push_end(End::Agent(l, 0));
+                        self.push_end(End::Agent(r, 0));
+                        self.code.push(Instruction::BIND);
+                    }
+                    (true, false) => {
+                        let r = self.place(right);
+                        self.occur(left, End::Agent(r, 0));
+                    }
+                    (false, true) => {
+                        let l = self.place(left);
+                        self.occur(right, End::Agent(l, 0));
+                    }
+                    (true, true) => {
+                        self.occur(left, End::Side(n, 0));
+                        self.occur(right, End::Side(n, 1));
+                    }
+                }
+            }
+            PrincipalType::Var => {
+                self.occur(&principal.left, End::Free);
+            }
+            PrincipalType::Cons => {
+                // Constant is not connected to anything
+                self.place(&principal.left);
             }
         }
-        index = index + 1;
     }
 
-    // Restore id of second agent from heap
-    code.push(Instruction::PUSH(0));
+    // Builds both sides of n-th connection of result
+    fn connect(&mut self, n: usize, principal: &Semantic::Principal) -> Result<(), CompileError> {
+        match principal.ptype {
+            PrincipalType::Pure => {
+                let (left, right) = (&principal.left, &principal.right);
+                match (left.atype == AgentType::Var, right.atype == AgentType::Var) {
+                    (false, false) => {
+                        // Bind agents by creating principal connection
+                        self.build(left)?;
+                        self.build(right)?;
+                        self.code.push(Instruction::BIND);
+                    }
+                    (true, false) => {
+                        self.build(right)?;
+                        let addr = self.store(right.span)?;
+                        self.occur(left, End::Slot(addr));
+                    }
+                    (false, true) => {
+                        self.build(left)?;
+                        let addr = self.store(left.span)?;
+                        self.occur(right, End::Slot(addr));
+                    }
+                    (true, true) => {
+                        self.occur(left, End::Side(n, 0));
+                        self.occur(right, End::Side(n, 1));
+                    }
+                }
+            }
+            PrincipalType::Var => {
+                self.occur(&principal.left, End::Free);
+            }
+            PrincipalType::Cons => {
+                // Constant is not connected to anything
+                self.build(&principal.left)?;
+                self.store(principal.left.span)?;
+            }
+        }
+        Ok(())
+    }
 
-    // 2. Delete (old) agents on left side
-    code.push(Instruction::DROP_AGENT);
-    code.push(Instruction::DROP_AGENT);
+    // Boundary agent of the initial net for every variable which occurs only once, returns name and id
+    // of every boundary agent
+    fn interface(&mut self, atype: u64) -> Vec<(String, u64)> {
+        let mut boundary = vec![];
+        for (name, span) in self.order.clone() {
+            if self.ends[&name].len() != 1 {
+                continue;
+            }
+            let id = self.next;
+            self.next += 1;
+            self.code.push(Instruction::GEN);
+            self.code.push(Instruction::CONST(atype));
+            self.code.push(Instruction::CONST(1));
+            self.code.push(Instruction::NEW_AGENT);
+            // Boundary agent is connected through its only auxiliary port
+            let var = Semantic::Agent { name: name.clone(), atype: AgentType::Var, ports: vec![], span };
+            self.occur(&var, End::Agent(id, 1));
+            boundary.push((name, id));
+        }
+        boundary
+    }
 
-    // 3. build up new graph and connect vars appropriately
+    // Follow wire of variable from one end to the other, crossing connections between variables
+    fn follow(&self, name: &String, from: End) -> End {
+        let mut name = name;
+        let mut at = from;
+        loop {
+            // Other occurrence of variable
+            let next = *self.ends[name].iter().find(|e| **e != at).unwrap_or(&End::Free);
+            match next {
+                End::Side(n, side) => {
+                    at = End::Side(n, 1 - side);
+                    name = &self.sides[&at];
+                }
+                end => return end,
+            }
+        }
+    }
 
-    // Create new connections
-    for principal in &equation.principals {
-        if principal.ptype == PrincipalType::Pure {
-            // Build agents and create auxiliary connections
-            code.append(&mut build_agent(&type_mapping, &variable_mapping, &principal.left));
-            code.append(&mut build_agent(&type_mapping, &variable_mapping, &principal.right));
+    // Push port at end of wire on stack, which is a new agent
+    fn push_end(&mut self, end: End) {
+        match end {
+            End::Slot(addr) => {
+                self.code.push(Instruction::PUSH(addr));
+                self.code.push(Instruction::PUSH(addr + 1));
+            }
+            End::Agent(id, pid) => {
+                self.code.push(Instruction::CONST(id));
+                self.code.push(Instruction::CONST(pid));
+            }
+            _ => unreachable!("end is not a new agent"),
+        }
+    }
 
-            // Bind agents by creating principal connection
-            code.push(Instruction::CONST(1));
-            code.push(Instruction::CONST(1));
-            code.push(Instruction::BIND);
+    // Connect ends of all wires
+    fn wire(&mut self) {
+        let mut done = vec![];
+        for (name, _) in self.order.clone() {
+            for end in self.ends[&name].clone() {
+                if let End::Side(_, _) = end {
+                    continue;
+                }
+                if done.contains(&end) {
+                    continue;
+                }
+                let other = self.follow(&name, end);
+                done.push(end);
+                done.push(other);
+                match (end, other) {
+                    (End::Slot(_) | End::Agent(_, _), End::Slot(_) | End::Agent(_, _)) => {
+                        // Both ends are new agents
+                        self.push_end(end);
+                        self.push_end(other);
+                        self.code.push(Instruction::BIND);
+                    }
+                    (End::Slot(a), End::Pair(aid, pid)) | (End::Pair(aid, pid), End::Slot(a)) => {
+                        // New agent takes over wire of active pair
+                        self.push_end(End::Slot(a));
+                        self.code.push(Instruction::PUSH(aid));
+                        self.code.push(Instruction::CONST(pid));
+                        self.code.push(Instruction::LINK);
+                    }
+                    (End::Pair(aid0, pid0), End::Pair(aid1, pid1)) => {
+                        // Neighbours of active pair get connected directly
+                        self.code.push(Instruction::PUSH(aid1));
+                        self.code.push(Instruction::CONST(pid1));
+                        self.code.push(Instruction::PORT);
+                        self.code.push(Instruction::PUSH(aid0));
+                        self.code.push(Instruction::CONST(pid0));
+                        self.code.push(Instruction::LINK);
+                    }
+                    _ => {
+                        // Wire ends nowhere, remaining end stays unconnected
+                    }
+                }
+            }
         }
-        else if principal.ptype == PrincipalType::Var {
-            code.append(&mut build_agent(&type_mapping, &variable_mapping, &principal.left));
+    }
+}
+
+pub fn compileEquation(equation: &Semantic::Equation,
+                       type_mapping: &HashMap<String, u64>,
+                       arities: &HashMap<String, usize>) -> Result<Vec<Instruction>, CompileError> {
+
+    // Wiring relies on every variable occurring exactly twice
+    let violations = check_equation(equation);
+    if !violations.is_empty() {
+        return Err(CompileError::Invalid(violations));
+    }
+
+    // Agents of active pair are stored at address 0 and 1, ports of new agents behind them
+    let mut builder = Builder::new(type_mapping, arities, 2);
+
+    // 1. Store ids of active pair, left agent is on top of stack
+    builder.code.push(Instruction::POP(0));
+    builder.code.push(Instruction::POP(1));
+
+    // Since on the left side only depth 1 agents are allowed every var is an auxiliary port of the pair
+    for (addr, agent) in [(0, &equation.left), (1, &equation.right)] {
+        if valued(&agent.name) {
+            builder.values.insert(agent.name.clone(), addr);
         }
-        else if principal.ptype == PrincipalType::Cons {
-            code.append(&mut build_agent(&type_mapping, &variable_mapping, &principal.left));
+        for (index, port) in agent.ports.iter().enumerate() {
+            if port.atype == AgentType::Var {
+                builder.occur(port, End::Pair(addr, index as u64 + 1));
+            }
         }
     }
 
-    return code;
-}
+    // 2. Build up new agents
+    for (n, principal) in equation.principals.iter().enumerate() {
+        builder.connect(n, principal)?;
+    }
 
-pub fn compileProgram(program: Semantic::Program) -> (VM, HashMap<String, u64>) {
+    // 3. Connect vars appropriately
+    builder.wire();
 
-    // VM
-    let mut vm = VM::new();
-    // Code
-    let mut code: Vec<Instruction> = Vec::new();
+    // 4. Delete (old) agents of active pair, remaining wires to them get disconnected
+    builder.code.push(Instruction::PUSH(0));
+    builder.code.push(Instruction::DROP_AGENT);
+    builder.code.push(Instruction::PUSH(1));
+    builder.code.push(Instruction::DROP_AGENT);
+
+    Ok(builder.code)
+}
+
+// Number every agent type of the program in order of first occurrence
+pub fn agent_types(program: &Semantic::Program) -> HashMap<String, u64> {
 
     // Create type mapping
-    let mut type_mapping: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    let type_mapping: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
 
-    fn traverse_types(agent: &Semantic::Agent, mut type_mapping: &RefCell<HashMap<String, u64>>) {
+    fn traverse_types(agent: &Semantic::Agent, type_mapping: &RefCell<HashMap<String, u64>>) {
 
         let mut handle = type_mapping.borrow_mut();
 
         // Traverse agent itself
-        if agent.atype != AgentType::Var {
-            if !(handle.contains_key(&agent.name)) {
-                let len = handle.len() as u64;
-                handle.insert(agent.name.clone(), len);
-            }
+        if agent.atype != AgentType::Var && !handle.contains_key(&agent.name) {
+            let len = handle.len() as u64;
+            handle.insert(agent.name.clone(), len);
         }
 
         // Release mutable borrow
@@ -174,7 +389,7 @@ pub fn compileProgram(program: Semantic::Program) -> (VM, HashMap<String, u64>)
 
         // Traverse ports
         for port in &agent.ports {
-            traverse_types(&port, &type_mapping);
+            traverse_types(port, type_mapping);
         }
     }
 
@@ -185,77 +400,120 @@ pub fn compileProgram(program: Semantic::Program) -> (VM, HashMap<String, u64>)
         // Traverse principals
         for principal in &equation.principals {
             traverse_types(&principal.left, &type_mapping);
-            traverse_types(&principal.right, &type_mapping);
+            // Right is a placeholder if there is no connection
+            if principal.ptype == PrincipalType::Pure {
+                traverse_types(&principal.right, &type_mapping);
+            }
+        }
+    }
+
+    // Traverse term
+    for term in &program.terms {
+        traverse_types(&term.left, &type_mapping);
+        if term.ptype == PrincipalType::Pure {
+            traverse_types(&term.right, &type_mapping);
+        }
+    }
+
+    // Declared agents might not be used at all
+    for signature in &program.signatures {
+        let mut handle = type_mapping.borrow_mut();
+        if !handle.contains_key(&signature.name) {
+            let len = handle.len() as u64;
+            handle.insert(signature.name.clone(), len);
         }
     }
 
+    type_mapping.into_inner()
+}
+
+pub fn compileProgram(program: Semantic::Program) -> Result<(VM, HashMap<String, u64>), CompileError> {
+
+    // Rules of built-in agents are compiled like the ones of the user
+    let program = with_builtins(&with_natives(&program));
+
+    // Reject programs violating the linearity condition or using agents inconsistently
+    let violations = check(&program);
+    if !violations.is_empty() {
+        return Err(CompileError::Invalid(violations));
+    }
+
+    // VM
+    let mut vm = VM::new();
+    // Code
+    let mut code: Vec<Instruction> = Vec::new();
+
+    // Create type mapping
+    let mut type_mapping = agent_types(&program);
+
+    // Number of auxiliary ports of every agent
+    let arities = infer_arities(&program).0;
+
     // Generate code for every equation
     for equation in program.equations {
         let rule_types = (
-            *type_mapping.clone().into_inner().get(&equation.left.name.clone()).unwrap(),
-            *type_mapping.clone().into_inner().get(&equation.right.name.clone()).unwrap());
-        let rule_code = compileEquation(&equation, &type_mapping.clone().into_inner());
+            *type_mapping.get(&equation.left.name.clone()).unwrap(),
+            *type_mapping.get(&equation.right.name.clone()).unwrap());
+        // Check made sure that repeated rules are equivalent
+        if vm.rules.contains_key(&rule_types) {
+            continue;
+        }
+        let rule_code = compileEquation(&equation, &type_mapping, &arities)?;
         // Save in vm
-        vm.rules.insert(rule_types, rule_code);
+        vm.new_rewrite(rule_types, rule_code)
+            .map_err(|error| CompileError::Bytecode { error, span: equation.span })?;
+        // Pair may also become active the other way round
+        if rule_types.0 != rule_types.1 {
+            let mirrored_code = compileEquation(&mirror(&equation), &type_mapping, &arities)?;
+            vm.new_rewrite((rule_types.1, rule_types.0), mirrored_code)
+                .map_err(|error| CompileError::Bytecode { error, span: equation.span })?;
+        }
     }
-
-    // Generate code for principal
-
-    // Construct agents
-    fn build_term(type_mapping: &HashMap<String, u64>, agent: &Semantic::Agent) -> Vec<Instruction> {
-        // Code
-        let mut code = vec![];
-        // Process agent
-        match agent.atype {
-            AgentType::Var => {
-                panic!("Vars not allowed here");
-            }
-            AgentType::Cons => {
-                // Id
-                code.push(Instruction::GEN);
-                // Save id for later
-                code.push(Instruction::DUP);
-                // Type
-                code.push(Instruction::CONST(type_mapping.get(&agent.name).unwrap().clone()));
-                // Create agent
-                code.push(Instruction::NEW_AGENT);
-            }
-            AgentType::Agent => {
-                // Id
-                code.push(Instruction::GEN);
-                // Save id for later
-                code.push(Instruction::DUP);
-                // Type
-                code.push(Instruction::CONST(type_mapping.get(&agent.name).unwrap().clone()));
-                // Create agent
-                code.push(Instruction::NEW_AGENT);
-                // Connect and create children
-                for port in &agent.ports {
-                    // Copy own id
-                    code.push(Instruction::DUP);
-                    // Create other agents
-                    code.append(&mut build_term(&type_mapping, &port));
-                    // Connect auxiliary ports
-                    code.push(Instruction::CONST(0));
-                    code.push(Instruction::CONST(0));
-                    code.push(Instruction::BIND);
-                }
-            }
+    // Operators on numbers are computed by the vm itself, rules written by the user take precedence
+    for (rule_types, rule_code) in native_rules(&type_mapping) {
+        if vm.rules.contains_key(&rule_types) {
+            continue;
         }
+        vm.new_rewrite(rule_types, rule_code)
+            .map_err(|error| CompileError::Bytecode { error, span: Span::default() })?;
+    }
 
-        return code;
+    // Generate code for initial net
+    // Boundary agents get a type of their own behind all agents of the program
+    let interface_type = type_mapping.len() as u64;
+    let mut builder = Builder::new(&type_mapping, &arities, 0);
+    // Ids are handed out in order on the empty net, the scratchpad is not needed to find the agents again
+    builder.next = vm.interaction_net.cnt + 1;
+    // Connections share their variables
+    for (n, term) in program.terms.iter().enumerate() {
+        builder.term(n, term);
+    }
+    // Variables occurring once are the interface of the net, twice they are a wire
+    let boundary = builder.interface(interface_type);
+    builder.wire();
+    code.append(&mut builder.code);
+    if !boundary.is_empty() {
+        type_mapping.insert(INTERFACE.to_string(), interface_type);
     }
-    // Create principal connection
-    code.append(&mut build_term(&type_mapping.clone().into_inner(), &program.term.left));
-    code.append(&mut build_term(&type_mapping.clone().into_inner(), &program.term.right));
-    code.push(Instruction::CONST(1));
-    code.push(Instruction::CONST(1));
-    code.push(Instruction::BIND);
     // Load code into vm
+    verify(&code, 0).map_err(|error| CompileError::Bytecode { error, span: Span::default() })?;
     vm.code = code;
-    // Run initial code to create principal connection
+    // Run initial code to create connections
     vm.run();
+    vm.interface = boundary.into_iter().collect();
 
     // Return finally prepared vm
-    return (vm, type_mapping.into_inner());
-}
\ No newline at end of file
+    Ok((vm, type_mapping))
+}
+
+// Find the equation a compiled rule was generated from
+pub fn rule_equation<'a>(program: &'a Semantic::Program,
+                         type_mapping: &HashMap<String, u64>,
+                         rule: (u64, u64)) -> Option<&'a Semantic::Equation> {
+    program.equations.iter().find(|equation| {
+        let left = type_mapping.get(&equation.left.name).copied();
+        let right = type_mapping.get(&equation.right.name).copied();
+        // Rules are registered in both orders
+        (left, right) == (Some(rule.0), Some(rule.1)) || (left, right) == (Some(rule.1), Some(rule.0))
+    })
+}
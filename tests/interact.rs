@@ -1,59 +1,132 @@
-#[path = "../src/interaction/mod.rs"]
-mod interaction;
-
-#[path = "../src/interaction/vm.rs"]
-mod vm;
+use YAIN::interaction;
+use YAIN::interaction::vm;
 
 mod unit_tests {
-    use crate::interaction::{Agent, InteractionNet};
+    use crate::interaction::{Agent, InteractionNet, FREE};
     use crate::vm;
-    use crate::vm::{Instruction, VM};
+    use crate::interaction::heap::Heap;
+    use crate::interaction::rules::RuleTable;
+    use crate::vm::{Instruction, Operator, ReduceError, StuckPair, StuckPolicy, VM};
     use crate::vm::Instruction::CONST;
+    use crate::interaction::verify::{verify, verify_local, verify_rule, VerifyError};
 
     #[test]
     pub fn test_innet() {
 
         let mut innet = InteractionNet::new();
-        innet.new_agent(1, 0);
-        innet.new_agent(2, 0);
-        innet.new_agent(3, 0);
+        innet.new_agent(1, 0, 1);
+        innet.new_agent(2, 0, 1);
+        innet.new_agent(3, 0, 1);
+
+        innet.bind_agents((1, 1), (2, 1));
+        innet.bind_agents((1, 0), (3, 0));
 
-        innet.bind_agents((false, false), 1, 2);
-        innet.bind_agents((true, true), 1, 3);
+        innet.bind_agents((2, 0), (3, 1));
 
-        innet.bind_agents((false, false), 2, 3);
+        assert_eq!(innet.active_pairs, vec![(1, 3)]);
 
         innet.drop_agent(1);
 
-        assert_eq!(innet.query_agent(2).ports.len(), 2);
-        assert_eq!(innet.query_agent(3).ports.len(), 2);
+        assert_eq!(innet.query_agent(2).ports, vec![(3, 1), FREE]);
+        assert_eq!(innet.query_agent(3).ports, vec![FREE, (2, 0)]);
     }
 
     #[test]
     pub fn test_net() {
         let mut innet = InteractionNet::new();
-        innet.new_agent(1, 1);
-        innet.new_agent(2, 1);
+        innet.new_agent(1, 1, 0);
+        innet.new_agent(2, 1, 1);
 
-        innet.bind_agents((true, false), 1, 2);
+        innet.bind_agents((1, 0), (2, 1));
 
         assert_eq!(innet.query_agent(1).id, 1);
         assert_eq!(innet.query_agent(1).atype, 1);
+        assert_eq!(innet.arity(2), 1);
 
-        assert_eq!(innet.query_agent(1), Agent { id: 1, atype: 1, ports: vec![2] });
-        assert_eq!(innet.query_agent(2), Agent { id: 2, atype: 1, ports: vec![0, 1] });
+        assert_eq!(innet.query_agent(1), Agent { id: 1, atype: 1, ports: vec![(2, 1)], value: 0 });
+        assert_eq!(innet.query_agent(2), Agent { id: 2, atype: 1, ports: vec![FREE, (1, 0)], value: 0 });
 
-        innet.unbind_agents(1, 2);
+        innet.unbind_agents((1, 0));
 
-        assert_eq!(innet.query_agent(1), Agent { id: 1, atype: 1, ports: vec![0] });
-        assert_eq!(innet.query_agent(2), Agent { id: 2, atype: 1, ports: vec![0] });
+        assert_eq!(innet.query_agent(1), Agent { id: 1, atype: 1, ports: vec![FREE], value: 0 });
+        assert_eq!(innet.query_agent(2), Agent { id: 2, atype: 1, ports: vec![FREE, FREE], value: 0 });
+    }
+
+    #[test]
+    pub fn test_ports() {
+        let mut innet = InteractionNet::new();
+        innet.new_agent(1, 0, 2);
+        innet.new_agent(2, 0, 2);
+        innet.new_agent(3, 0, 1);
+
+        // Two wires between the same agents, crossed
+        innet.bind_agents((1, 1), (2, 2));
+        innet.bind_agents((1, 2), (2, 1));
+
+        assert_eq!(innet.port((1, 1)), (2, 2));
+        assert_eq!(innet.port((1, 2)), (2, 1));
+        assert_eq!(innet.port((2, 1)), (1, 2));
+
+        // Agent 3 takes over first wire
+        innet.link((3, 1), (1, 1));
+
+        assert_eq!(innet.port((1, 1)), FREE);
+        assert_eq!(innet.port((2, 2)), (3, 1));
+        assert_eq!(innet.port((3, 1)), (2, 2));
+
+        // Only the wire still ending at agent 1 is removed
+        innet.drop_agent(1);
+
+        assert_eq!(innet.query_agent(2).ports, vec![FREE, FREE, (3, 1)]);
+        assert!(innet.active_pairs.is_empty());
+    }
+
+    #[test]
+    pub fn test_heap() {
+        let mut innet = InteractionNet::new();
+        let a = innet.gen_id();
+        let b = innet.gen_id();
+        innet.new_agent(a, 0, 1);
+        innet.new_agent(b, 0, 1);
+        innet.bind_agents((a, 1), (b, 1));
+        assert_eq!(innet.heap.len(), 2);
+
+        // Id of dropped agent gets reused
+        innet.drop_agent(a);
+        assert_eq!(innet.heap.len(), 1);
+        assert!(!innet.heap.contains(a));
+        assert_eq!(innet.gen_id(), a);
+        assert_eq!(innet.gen_id(), b + 1);
+
+        // Id taken by hand is not handed out again
+        innet.drop_agent(b);
+        innet.new_agent(b, 0, 0);
+        assert_eq!(innet.gen_id(), b + 2);
+
+        // Agents are listed in order of their ids
+        innet.new_agent(a, 1, 0);
+        let ids: Vec<u64> = innet.heap.values().map(|agent| agent.id).collect();
+        assert_eq!(ids, vec![a, b]);
+
+        // Sparse heap holds agents with large ids without growing
+        let mut heap = Heap::sparse();
+        heap.insert(Agent { id: u64::MAX - 1, atype: 0, ports: vec![FREE], value: 0 });
+        heap.insert(Agent { id: 5, atype: 0, ports: vec![FREE], value: 0 });
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.get(u64::MAX - 1).map(|agent| agent.id), Some(u64::MAX - 1));
+        let ids: Vec<u64> = heap.values().map(|agent| agent.id).collect();
+        assert_eq!(ids, vec![5, u64::MAX - 1]);
+        assert!(heap.take(5).is_some());
+        assert!(heap.remove(u64::MAX - 1).is_some());
+        assert!(heap.is_empty());
+        assert_eq!(heap.recycle(), Some(u64::MAX - 1));
     }
 
     #[test]
     #[should_panic]
     pub fn test_panic() {
         let mut innet = InteractionNet::new();
-        innet.new_agent(1, 1);
+        innet.new_agent(1, 1, 0);
         innet.drop_agent(1);
         // Should panic here
         innet.query_agent(1);
@@ -73,9 +146,10 @@ mod unit_tests {
         let mut vm = VM::new();
         vm.stack.push(1);
         vm.stack.push(1);
+        vm.stack.push(0);
         vm.code = vec![vm::Instruction::NEW_AGENT];
         vm.run();
-        assert_eq!(vm.interaction_net.query_agent(1), Agent{id: 1, atype: 1, ports: vec![0]});
+        assert_eq!(vm.interaction_net.query_agent(1), Agent{id: 1, atype: 1, ports: vec![FREE], value: 0});
         vm.code.push(CONST(1));
         vm.code.push(Instruction::DROP_AGENT);
         vm.run();
@@ -86,11 +160,11 @@ mod unit_tests {
     #[test]
     pub fn test_vm_reduce() {
         let mut vm = VM::new();
-        vm.new_rewrite((1, 1), vec![Instruction::POP(1), Instruction::POP(2)]);
-        vm.interaction_net.new_agent(2, 1);
-        vm.interaction_net.new_agent(3, 1);
-        vm.interaction_net.bind_agents((true, true), 2, 3);
-        vm.reduce();
+        vm.new_rewrite((1, 1), vec![Instruction::POP(1), Instruction::POP(2)]).unwrap();
+        vm.interaction_net.new_agent(2, 1, 0);
+        vm.interaction_net.new_agent(3, 1, 0);
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        vm.reduce().unwrap();
         assert_eq!(vm.scratchpad[1], 2);
         assert_eq!(vm.scratchpad[2], 3);
     }
@@ -102,28 +176,850 @@ mod unit_tests {
             Instruction::GEN,
             Instruction::DUP,
             Instruction::CONST(1),
+            Instruction::CONST(1),
             Instruction::NEW_AGENT,
             Instruction::GEN,
             Instruction::DUP,
             Instruction::CONST(1),
+            Instruction::CONST(1),
             Instruction::NEW_AGENT,
             Instruction::POP(0),
             Instruction::POP(1),
             Instruction::PUSH(0),
+            Instruction::CONST(1),
             Instruction::PUSH(1),
             Instruction::CONST(0),
-            Instruction::CONST(0),
             Instruction::BIND,
-            Instruction::PUSH(0),
             Instruction::PUSH(1),
+            Instruction::CONST(0),
+            Instruction::PORT,
+            Instruction::POP(3),
+            Instruction::POP(2),
+            Instruction::PUSH(0),
+            Instruction::CONST(1),
             Instruction::UNBIND,
         ];
         vm.run();
 
         assert_eq!(vm.interaction_net.heap.len(), 2);
-        assert_eq!(vm.interaction_net.heap.get(&2).unwrap().ports.len(), 1);
-        assert_eq!(vm.interaction_net.heap.get(&3).unwrap().ports.len(), 1);
+        assert_eq!(vm.interaction_net.heap.get(2).unwrap().ports, vec![FREE, FREE]);
+        assert_eq!(vm.interaction_net.heap.get(3).unwrap().ports, vec![FREE, FREE]);
         assert_eq!(vm.scratchpad[0], 3);
         assert_eq!(vm.scratchpad[1], 2);
+        assert_eq!((vm.scratchpad[2], vm.scratchpad[3]), (3, 1));
+    }
+
+    #[test]
+    pub fn test_vm_link() {
+        let mut vm = VM::new();
+        vm.interaction_net.new_agent(2, 1, 1);
+        vm.interaction_net.new_agent(3, 1, 1);
+        vm.interaction_net.new_agent(4, 1, 0);
+        vm.interaction_net.bind_agents((2, 1), (3, 0));
+        // Move wire of (2, 1) to principal port of 4
+        vm.code = vec![
+            Instruction::CONST(4),
+            Instruction::CONST(0),
+            Instruction::CONST(2),
+            Instruction::CONST(1),
+            Instruction::LINK,
+        ];
+        vm.run();
+
+        assert_eq!(vm.interaction_net.port((2, 1)), FREE);
+        assert_eq!(vm.interaction_net.port((4, 0)), (3, 0));
+        // Two principal ports got connected
+        assert_eq!(vm.interaction_net.active_pairs, vec![(4, 3)]);
+    }
+
+    #[test]
+    pub fn test_vm_values() {
+        let mut vm = VM::new();
+        vm.interaction_net.new_agent(2, 1, 0);
+        vm.interaction_net.new_agent(3, 1, 0);
+        // 2 gets 7, 3 gets 7 - 9 and 7 * 6
+        vm.code = vec![
+            Instruction::CONST(2),
+            Instruction::CONST(7),
+            Instruction::STORE,
+            Instruction::CONST(3),
+            Instruction::CONST(2),
+            Instruction::LOAD,
+            Instruction::CONST(9),
+            Instruction::OPERATE(Operator::Sub),
+            Instruction::STORE,
+            Instruction::CONST(2),
+            Instruction::LOAD,
+            Instruction::CONST(6),
+            Instruction::OPERATE(Operator::Mul),
+        ];
+        vm.run();
+
+        assert_eq!(vm.interaction_net.value(2), 7);
+        // Subtraction stops at 0
+        assert_eq!(vm.interaction_net.value(3), 0);
+        assert_eq!(vm.stack, vec![42]);
+
+        assert_eq!(Operator::Add.apply(u64::MAX, 2), 1);
+        assert_eq!(Operator::Less.apply(3, 4), 1);
+        assert_eq!(Operator::Less.apply(4, 4), 0);
+        assert_eq!(Operator::Equal.apply(4, 4), 1);
+    }
+
+    #[test]
+    pub fn test_bytecode() {
+        use crate::interaction::bytecode::{from_bytes, load, to_bytes, write, LoadError};
+        use std::collections::HashMap;
+
+        // Rule (1, 2) deletes the pair, initial code creates it
+        let mut vm = VM::new();
+        vm.new_rewrite((1, 2), vec![Instruction::DROP_AGENT, Instruction::DROP_AGENT]).unwrap();
+        vm.new_rewrite((2, 1), vec![Instruction::OPERATE(Operator::Less), Instruction::POP(0)]).unwrap();
+        vm.code = vec![
+            Instruction::GEN,
+            Instruction::DUP,
+            Instruction::CONST(1),
+            Instruction::CONST(0),
+            Instruction::NEW_AGENT,
+            Instruction::CONST(0),
+            Instruction::GEN,
+            Instruction::DUP,
+            Instruction::CONST(2),
+            Instruction::CONST(0),
+            Instruction::NEW_AGENT,
+            Instruction::CONST(0),
+            Instruction::BIND,
+        ];
+        vm.interface.insert("x".to_string(), 2);
+        let type_mapping = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+
+        let mut file = vec![];
+        write(&vm, &type_mapping, &mut file).unwrap();
+        assert_eq!(file, to_bytes(&vm, &type_mapping));
+        assert_eq!(&file[..5], b"YAINC");
+
+        let (mut loaded, types) = load(&mut &file[..]).unwrap();
+        assert_eq!(types, type_mapping);
+        assert_eq!(loaded.code, vm.code);
+        assert_eq!(loaded.interface, vm.interface);
+        assert_eq!(loaded.rules.iter().collect::<Vec<_>>(), vm.rules.iter().collect::<Vec<_>>());
+        // Initial net is built while loading
+        assert_eq!(loaded.interaction_net.heap.len(), 2);
+        assert_eq!(loaded.interaction_net.active_pairs, vec![(2, 3)]);
+        assert!(loaded.reduce().unwrap().is_empty());
+        assert!(loaded.interaction_net.heap.is_empty());
+
+        // Broken files
+        assert!(matches!(from_bytes(b"YAIN"), Err(LoadError::NotBytecode)));
+        let mut version = file.clone();
+        version[5] = 7;
+        assert!(matches!(from_bytes(&version), Err(LoadError::Version(7))));
+        assert!(matches!(from_bytes(&file[..file.len() - 1]), Err(LoadError::Truncated)));
+        let mut trailing = file.clone();
+        trailing.push(0);
+        assert!(matches!(from_bytes(&trailing), Err(LoadError::Trailing { offset }) if offset == file.len()));
+        // Opcode of first instruction of initial code
+        let mut opcode = file.clone();
+        let offset = file.len() - (4 + 4 + 1 + 8) - (6 * 9 + 7);
+        assert_eq!(opcode[offset], 1);
+        opcode[offset] = 99;
+        assert!(matches!(from_bytes(&opcode), Err(LoadError::Opcode { offset: o, opcode: 99 }) if o == offset));
+        // Code which would break the vm is rejected
+        let mut broken = VM::new();
+        broken.rules.insert((1, 1), vec![Instruction::NOP, Instruction::POP(u64::MAX)]);
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Verify { rule: Some((1, 1)), error: VerifyError::Address { pc: 1, .. } })));
+        let mut broken = VM::new();
+        broken.code = vec![Instruction::GEN, Instruction::BIND];
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Verify { rule: None, error: VerifyError::Underflow { pc: 1, .. } })));
+        // Rules for types outside the type table
+        let mut broken = VM::new();
+        broken.rules.insert((u64::MAX, 0), vec![]);
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Type { rule: (u64::MAX, 0) })));
+        // Initial code using agents it did not create
+        let mut broken = VM::new();
+        broken.code = vec![Instruction::CONST(5), Instruction::DROP_AGENT];
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Run(vm::Fault { pc: 1, instruction: Instruction::DROP_AGENT }))));
+        let mut broken = VM::new();
+        broken.code = vm.code[..6].to_vec();
+        broken.code.extend([Instruction::CONST(3), Instruction::CONST(0), Instruction::BIND]);
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Run(vm::Fault { pc: 8, .. }))));
+        let mut broken = VM::new();
+        broken.code = vec![Instruction::CONST(1), Instruction::CONST(1), Instruction::CONST(u64::MAX), Instruction::NEW_AGENT];
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Run(vm::Fault { pc: 3, .. }))));
+        // Boundary agents must exist
+        let mut broken = VM::new();
+        broken.interface.insert("x".to_string(), 2);
+        let file = to_bytes(&broken, &type_mapping);
+        assert!(matches!(from_bytes(&file), Err(LoadError::Interface { id: 2, .. })));
+    }
+
+    #[test]
+    pub fn test_assembly() {
+        use crate::interaction::assembly::{assemble, disassemble, disassemble_code, AsmError};
+        use std::collections::HashMap;
+
+        let names = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+        let text = "
+        ; Pair of A and B vanishes
+        A # B:
+            DROP_AGENT
+            DROP_AGENT  ; right agent
+        2 # 1 :
+            OPERATE ==
+            POP 0
+        init:
+            GEN
+            DUP
+            CONST 1
+            CONST 0
+            NEW_AGENT
+            CONST 0
+            GEN
+            DUP
+            CONST 2
+            CONST 0
+            NEW_AGENT
+            CONST 0
+            BIND
+        ";
+        let assembly = assemble(text, &names).unwrap();
+        assert_eq!(assembly.rules, vec![
+            ((1, 2), vec![Instruction::DROP_AGENT, Instruction::DROP_AGENT]),
+            ((2, 1), vec![Instruction::OPERATE(Operator::Equal), Instruction::POP(0)]),
+        ]);
+
+        let mut vm = VM::new();
+        assembly.apply(&mut vm).unwrap();
+        vm.run();
+        assert!(vm.reduce().unwrap().is_empty());
+        assert!(vm.interaction_net.heap.is_empty());
+
+        // Listing assembles to the same rules and code
+        let listing = disassemble(&vm, &names);
+        assert!(listing.starts_with("A # B:\n    DROP_AGENT\n    DROP_AGENT\nB # A:\n    OPERATE ==\n    POP 0\ninit:\n    GEN\n"));
+        assert_eq!(assemble(&listing, &names).unwrap(), assembly);
+        assert_eq!(disassemble_code(&[Instruction::CONST(3), Instruction::PUSH(1), Instruction::NEW_AGENT]),
+                   "    CONST 3\n    PUSH 1\n    NEW_AGENT\n");
+        // Rules can be patched without touching the initial code
+        let patch = assemble("1 # 2:\n    POP 0\n    POP 1", &HashMap::new()).unwrap();
+        assert_eq!(patch.code, None);
+        patch.apply(&mut vm).unwrap();
+        assert_eq!(vm.rules.get(&(1, 2)), Some(&[Instruction::POP(0), Instruction::POP(1)][..]));
+        assert_eq!(vm.code.len(), 13);
+
+        // Errors point to their line
+        assert_eq!(assemble("init:\n    JUMP 3", &names), Err(AsmError::Mnemonic { line: 2, name: "JUMP".to_string() }));
+        assert_eq!(assemble("init:\n    CONST", &names), Err(AsmError::Operand { line: 2, text: "CONST".to_string() }));
+        assert_eq!(assemble("init:\n    BIND 1", &names), Err(AsmError::Operand { line: 2, text: "BIND 1".to_string() }));
+        assert_eq!(assemble("init:\n    OPERATE /", &names), Err(AsmError::Operand { line: 2, text: "OPERATE /".to_string() }));
+        assert_eq!(assemble("A # C:", &names), Err(AsmError::Type { line: 1, name: "C".to_string() }));
+        assert_eq!(assemble("main:", &names), Err(AsmError::Label { line: 1, text: "main:".to_string() }));
+        assert_eq!(assemble("\n    GEN", &names), Err(AsmError::Unlabeled { line: 2 }));
+        assert_eq!(assemble("A # B:\n1 # 2:", &names), Err(AsmError::Duplicate { line: 2, text: "1 # 2:".to_string() }));
+        assert!(matches!(assemble("A # B:\n    POP 0\n\n    POP 1\n    POP 2", &names),
+                         Err(AsmError::Verify { line: 5, error: VerifyError::Underflow { pc: 2, .. } })));
+        assert!(matches!(assemble("init:\n    GEN\nA # B:\n    POP 0\n    POP 1", &names),
+                         Err(AsmError::Verify { line: 2, error: VerifyError::Unbalanced { depth: 1 } })));
+    }
+
+    #[test]
+    pub fn test_verify() {
+        // Rule pops both ids of the active pair
+        assert_eq!(verify_rule(&[Instruction::POP(0), Instruction::POP(1)]), Ok(()));
+        assert_eq!(verify(&[Instruction::GEN, Instruction::DUP, Instruction::UNBIND], 0), Ok(()));
+        assert_eq!(verify_rule(&[Instruction::POP(0), Instruction::BIND]),
+                   Err(VerifyError::Underflow { pc: 1, instruction: Instruction::BIND, depth: 1 }));
+        assert_eq!(verify_rule(&[Instruction::POP(vm::SCRATCHPAD_SIZE as u64), Instruction::POP(0)]),
+                   Err(VerifyError::Address { pc: 0, instruction: Instruction::POP(vm::SCRATCHPAD_SIZE as u64) }));
+        assert_eq!(verify_rule(&[Instruction::DUP]), Err(VerifyError::Unbalanced { depth: 3 }));
+        assert_eq!(verify(&[], 0), Ok(()));
+
+        // Broken rules are not installed
+        let mut vm = VM::new();
+        assert_eq!(vm.new_rewrite((1, 2), vec![Instruction::PORT, Instruction::LINK]),
+                   Err(VerifyError::Underflow { pc: 1, instruction: Instruction::LINK, depth: 2 }));
+        assert!(!vm.rules.contains_key(&(1, 2)));
+        assert!(vm.new_rewrite((1, 2), vec![Instruction::PORT, Instruction::UNBIND]).is_ok());
+        assert!(vm.rules.contains_key(&(1, 2)));
+    }
+
+    #[test]
+    pub fn test_rule_table() {
+        let mut rules = RuleTable::new();
+        rules.insert((0, 1), vec![Instruction::NOP, Instruction::NOP]);
+        rules.insert((1, 0), vec![Instruction::DUP]);
+        // Table grows with new agent types
+        rules.insert((3, 2), vec![Instruction::GEN]);
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules.get(&(0, 1)), Some(&[Instruction::NOP, Instruction::NOP][..]));
+        assert_eq!(rules.get(&(1, 0)), Some(&[Instruction::DUP][..]));
+        assert_eq!(rules.get(&(3, 2)), Some(&[Instruction::GEN][..]));
+        assert!(!rules.contains_key(&(2, 3)));
+        assert!(!rules.contains_key(&(7, 7)));
+
+        // Replacing keeps number of rules
+        rules.insert((1, 0), vec![Instruction::POP(0), Instruction::POP(1)]);
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules.get(&(1, 0)), Some(&[Instruction::POP(0), Instruction::POP(1)][..]));
+
+        rules.remove(&(0, 1));
+        assert_eq!(rules.len(), 2);
+        let pairs: Vec<(u64, u64)> = rules.iter().map(|(atypes, _)| atypes).collect();
+        assert_eq!(pairs, vec![(1, 0), (3, 2)]);
+
+        // Large types do not blow up the index
+        rules.insert((u64::MAX, 0), vec![Instruction::NOP]);
+        rules.insert((99999999, 1), vec![Instruction::DUP]);
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules.get(&(u64::MAX, 0)), Some(&[Instruction::NOP][..]));
+        assert_eq!(rules.get(&(99999999, 1)), Some(&[Instruction::DUP][..]));
+        assert!(!rules.contains_key(&(0, u64::MAX)));
+        let pairs: Vec<(u64, u64)> = rules.iter().map(|(atypes, _)| atypes).collect();
+        assert_eq!(pairs, vec![(1, 0), (3, 2), (99999999, 1), (u64::MAX, 0)]);
+        rules.remove(&(u64::MAX, 0));
+        rules.remove(&(99999999, 1));
+        assert_eq!(rules.len(), 2);
+
+        // Rules run from the table without being loaded into the code of the vm
+        let mut vm = VM::new();
+        vm.rules = rules;
+        vm.interaction_net.new_agent(2, 1, 0);
+        vm.interaction_net.new_agent(3, 0, 0);
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        vm.reduce().unwrap();
+        assert!(vm.code.is_empty());
+        assert_eq!((vm.scratchpad[0], vm.scratchpad[1]), (2, 3));
+    }
+
+    // Rule for (1, 1) which creates an active pair of type (2, 2)
+    fn stuck_vm() -> VM {
+        let mut vm = VM::new();
+        vm.new_rewrite((1, 1), vec![
+            Instruction::DROP_AGENT,
+            Instruction::DROP_AGENT,
+            Instruction::GEN,
+            Instruction::DUP,
+            Instruction::CONST(2),
+            Instruction::CONST(0),
+            Instruction::NEW_AGENT,
+            Instruction::CONST(0),
+            Instruction::GEN,
+            Instruction::DUP,
+            Instruction::CONST(2),
+            Instruction::CONST(0),
+            Instruction::NEW_AGENT,
+            Instruction::CONST(0),
+            Instruction::BIND,
+        ]).unwrap();
+        vm.interaction_net.new_agent(10, 1, 0);
+        vm.interaction_net.new_agent(11, 1, 0);
+        vm.interaction_net.bind_agents((10, 0), (11, 0));
+        vm
+    }
+
+    #[test]
+    pub fn test_stuck_pairs() {
+        // New agents reuse the ids of the dropped pair
+        let expected = StuckPair { pair: (11, 10), atypes: (2, 2), origin: Some((1, 1)) };
+
+        // Collect
+        let mut vm = stuck_vm();
+        assert_eq!(vm.reduce(), Ok(vec![expected.clone()]));
+        assert!(vm.interaction_net.active_pairs.is_empty());
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+
+        // Keep
+        let mut vm = stuck_vm();
+        vm.policy = StuckPolicy::Keep;
+        assert_eq!(vm.reduce(), Ok(vec![expected.clone()]));
+        assert_eq!(vm.interaction_net.active_pairs, vec![(11, 10)]);
+        assert_eq!(vm.interaction_net.origins, vec![Some((1, 1))]);
+        // Pair stays reported when reducing again
+        assert_eq!(vm.reduce(), Ok(vec![expected.clone()]));
+
+        // Error
+        let mut vm = stuck_vm();
+        vm.policy = StuckPolicy::Error;
+        assert_eq!(vm.reduce(), Err(ReduceError::NoRule(expected)));
+        assert_eq!(vm.interaction_net.active_pairs, vec![(11, 10)]);
+
+        // Pairs of the initial net have no origin
+        let mut vm = VM::new();
+        vm.interaction_net.new_agent(2, 1, 0);
+        vm.interaction_net.new_agent(3, 2, 0);
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        assert_eq!(vm.reduce(), Ok(vec![StuckPair { pair: (2, 3), atypes: (1, 2), origin: None }]));
+    }
+
+    #[test]
+    pub fn test_budget() {
+        use crate::vm::{Budget, CancelHandle, Outcome};
+
+        // Pair of type (2, 2) recreates a pair of type (1, 1) and the other way round
+        let looping = || {
+            let mut vm = stuck_vm();
+            let code: Vec<Instruction> = vm.rules.get(&(1, 1)).unwrap().iter()
+                .map(|instruction| if *instruction == CONST(2) { CONST(1) } else { *instruction })
+                .collect();
+            vm.new_rewrite((2, 2), code).unwrap();
+            vm
+        };
+        let length = looping().rules.get(&(1, 1)).unwrap().len() as u64;
+
+        let mut vm = looping();
+        assert_eq!(vm.reduce_with_limit(10), Ok(Outcome::Exhausted(vec![])));
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+        assert_eq!(vm.interaction_net.active_pairs.len(), 1);
+        // Reduction resumes where it stopped
+        assert_eq!(vm.reduce_with_limit(1), Ok(Outcome::Exhausted(vec![])));
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+
+        // Rules are run as a whole or not at all
+        let mut vm = looping();
+        let budget = Budget { instructions: Some(3 * length - 1), ..Budget::default() };
+        assert_eq!(vm.reduce_with_budget(&budget), Ok(Outcome::Exhausted(vec![])));
+        assert_eq!(vm.interaction_net.active_pairs.len(), 1);
+        assert!(vm.stack.is_empty());
+        let pair = vm.interaction_net.active_pairs[0];
+        // Two rules ran, the pair was created by the second one
+        assert_eq!(vm.interaction_net.atype(pair.0), 1);
+
+        // Cancelled before the first interaction
+        let mut vm = looping();
+        let cancel = CancelHandle::new();
+        cancel.cancel();
+        let budget = Budget { cancel: Some(cancel), ..Budget::default() };
+        assert_eq!(vm.reduce_with_budget(&budget), Ok(Outcome::Cancelled(vec![])));
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+
+        // Cancelled from another thread
+        let mut vm = looping();
+        let cancel = CancelHandle::new();
+        let budget = Budget { cancel: Some(cancel.clone()), ..Budget::default() };
+        let outcome = std::thread::scope(|scope| {
+            let reduction = scope.spawn(|| vm.reduce_with_budget(&budget));
+            cancel.cancel();
+            reduction.join().unwrap()
+        });
+        assert_eq!(outcome, Ok(Outcome::Cancelled(vec![])));
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+
+        // Terminating net reaches normal form within the limit
+        let mut vm = stuck_vm();
+        let outcome = vm.reduce_with_limit(5).unwrap();
+        assert!(matches!(outcome, Outcome::Normal(_)));
+        assert_eq!(outcome.stuck().len(), 1);
+
+        // Pairs without rule stay in net when reduction stops early
+        let mut vm = stuck_vm();
+        vm.interaction_net.new_agent(20, 3, 0);
+        vm.interaction_net.new_agent(21, 3, 0);
+        vm.interaction_net.bind_agents((20, 0), (21, 0));
+        let expected = StuckPair { pair: (20, 21), atypes: (3, 3), origin: None };
+        assert_eq!(vm.reduce_with_limit(1), Ok(Outcome::Exhausted(vec![expected.clone()])));
+        assert_eq!(vm.interaction_net.active_pairs, vec![(11, 10), (20, 21)]);
+        // and are reported again once normal form is reached
+        assert_eq!(vm.reduce().unwrap().len(), 2);
+        assert!(vm.interaction_net.active_pairs.is_empty());
+    }
+
+    #[test]
+    pub fn test_stats() {
+        use crate::interaction::stats::{RuleStats, Stats};
+
+        // Only interactions and peaks are counted without profiling
+        let mut vm = stuck_vm();
+        vm.reduce().unwrap();
+        assert_eq!(vm.stats.interactions, 1);
+        assert!(vm.stats.rules.is_empty());
+        assert_eq!(vm.stats.executed(), 0);
+
+        let mut vm = stuck_vm();
+        vm.profile = true;
+        vm.reduce().unwrap();
+        let length = vm.rules.get(&(1, 1)).unwrap().len() as u64;
+        assert_eq!(vm.rules.kinds((1, 1)).unwrap().iter().sum::<u64>(), length);
+        assert_eq!(vm.stats.interactions, 1);
+        assert_eq!(vm.stats.rules.get(&(1, 1)), Some(&RuleStats { interactions: 1, instructions: length }));
+        assert_eq!(vm.stats.executed(), length);
+        assert_eq!(vm.stats.count(Instruction::GEN), 2);
+        assert_eq!((vm.stats.agents_created(), vm.stats.agents_dropped()), (2, 2));
+        assert_eq!((vm.stats.peak_heap, vm.stats.peak_active_pairs), (2, 1));
+
+        // Counters add up over reductions until reset
+        vm.interaction_net.new_agent(12, 1, 0);
+        vm.interaction_net.new_agent(13, 1, 0);
+        vm.interaction_net.new_agent(14, 1, 0);
+        vm.interaction_net.new_agent(15, 1, 0);
+        vm.interaction_net.bind_agents((12, 0), (13, 0));
+        vm.interaction_net.bind_agents((14, 0), (15, 0));
+        vm.reduce().unwrap();
+        assert_eq!(vm.stats.interactions, 3);
+        assert_eq!(vm.stats.peak_heap, 6);
+        assert_eq!(vm.stats.peak_active_pairs, 2);
+        vm.stats = Stats::default();
+        assert_eq!(vm.stats.executed(), 0);
+    }
+
+    #[test]
+    pub fn test_debugger() {
+        use crate::interaction::debug::{Breakpoint, Debugger, Stop};
+        use std::collections::HashMap;
+
+        let names = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+        let mut vm = stuck_vm();
+        let mut debugger = Debugger::new(&mut vm, &names);
+        assert_eq!(debugger.step(), Stop::Started { pair: (10, 11), atypes: (1, 1) });
+        assert_eq!(debugger.vm.stack, vec![11, 10]);
+        assert_eq!(debugger.step(), Stop::Executed { offset: 0, instruction: Instruction::DROP_AGENT });
+        assert_eq!(debugger.position(), "A # A on pair 10 11, next 1: DROP_AGENT\n");
+
+        // Stop before the first agent of type B is created
+        debugger.breakpoints.push(Breakpoint::Create(2));
+        assert_eq!(debugger.cont(), Stop::Breakpoint(0));
+        assert_eq!(debugger.position(), "A # A on pair 10 11, next 6: NEW_AGENT\n");
+        assert_eq!(debugger.vm.stack, vec![11, 11, 2, 0]);
+        // Second agent of type B is created by the same interaction
+        assert_eq!(debugger.cont(), Stop::Breakpoint(0));
+        assert_eq!(debugger.next(), Stop::Finished { atypes: (1, 1) });
+        assert!(debugger.vm.stack.is_empty());
+        assert_eq!(debugger.neighbourhood(10), "10 B\n  port 0 -> 11 B port 0\n");
+
+        let stuck = StuckPair { pair: (11, 10), atypes: (2, 2), origin: Some((1, 1)) };
+        assert_eq!(debugger.step(), Stop::NoRule(stuck.clone()));
+        assert_eq!(debugger.step(), Stop::Normal(vec![stuck.clone()]));
+        assert_eq!(debugger.cont(), Stop::Normal(vec![]));
+
+        // Commands
+        let mut vm = stuck_vm();
+        vm.policy = StuckPolicy::Error;
+        let mut debugger = Debugger::new(&mut vm, &names);
+        assert_eq!(debugger.command("break A # A").unwrap(), "breakpoint 0: A # A\n");
+        assert_eq!(debugger.command("c").unwrap(), "breakpoint 0: A # A\nA # A on pair 10 11, next 0: DROP_AGENT\n");
+        assert_eq!(debugger.command("s").unwrap(), "A # A on pair 10 11, next 1: DROP_AGENT\n");
+        assert_eq!(debugger.command("stack").unwrap(), "[11]\n");
+        assert_eq!(debugger.command("delete 0").unwrap(), "");
+        assert_eq!(debugger.command("c").unwrap(), "stopped, no rule for B # B on pair 11 10\n");
+        assert_eq!(debugger.command("scratchpad").unwrap(), "");
+        assert_eq!(debugger.command("agent 99").unwrap(), "no agent 99\n");
+        assert!(debugger.command("break new C").unwrap().starts_with("expected"));
+        assert!(debugger.command("jump").unwrap().starts_with("unknown command"));
+        assert_eq!(debugger.command("quit"), None);
+
+        // Leaving the debugger in the middle of an interaction finishes it
+        let mut vm = stuck_vm();
+        let mut output = vec![];
+        Debugger::new(&mut vm, &names).repl(&mut &b"step\nstep\nquit\n"[..], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "(yain) A # A on pair 10 11, next 0: DROP_AGENT\n(yain) A # A on pair 10 11, next 1: DROP_AGENT\n(yain) ");
+        assert!(vm.stack.is_empty());
+        assert_eq!(vm.reduce().unwrap().len(), 1);
+
+        // Addresses written while debugging are shown even when they hold 0
+        let mut vm = VM::new();
+        vm.new_rewrite((1, 1), vec![Instruction::DROP_AGENT, Instruction::DROP_AGENT, Instruction::CONST(0), Instruction::POP(3)]).unwrap();
+        vm.interaction_net.new_agent(2, 1, 0);
+        vm.interaction_net.new_agent(3, 1, 0);
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        let mut debugger = Debugger::new(&mut vm, &names);
+        assert_eq!(debugger.command("next").unwrap(), "finished A # A\nbetween interactions, 0 active pair(s)\n");
+        assert_eq!(debugger.command("scratchpad").unwrap(), "3: 0\n");
+        assert_eq!(vm.stats.interactions, 1);
+        assert_eq!(vm.interaction_net.origin, None);
+    }
+
+    #[test]
+    pub fn test_dot() {
+        use crate::interaction::dot::{frames, to_dot};
+        use std::collections::HashMap;
+
+        let names = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+        let mut net = InteractionNet::new();
+        net.new_agent(2, 1, 1);
+        net.new_agent(3, 2, 0);
+        net.new_agent(4, 1, 0);
+        net.heap.get_mut(4).unwrap().value = 7;
+        net.bind_agents((2, 0), (3, 0));
+        net.bind_agents((2, 1), (4, 0));
+        let dot = to_dot(&net, &names, "");
+        assert!(dot.starts_with("digraph net {\n  node"));
+        assert!(dot.contains("  a2 [label=\"A\", style=\"rounded,filled\", fillcolor=lightpink];\n"));
+        assert!(dot.contains("  a4 [label=\"A 7\"];\n"));
+        // Wires are drawn once, the wire of the active pair is highlighted
+        assert!(dot.contains("  a2 -> a3 [arrowtail=dot, arrowhead=dot, taillabel=\"\", headlabel=\"\", color=red, penwidth=2];\n"));
+        assert!(dot.contains("  a2 -> a4 [arrowtail=none, arrowhead=dot, taillabel=\"1\", headlabel=\"\"];\n"));
+        assert_eq!(dot.matches(" -> ").count(), 2);
+        assert!(to_dot(&net, &names, "step \"1\"").contains("  label=\"step \\\"1\\\"\";\n"));
+
+        // One frame before and one after every interaction
+        let mut vm = stuck_vm();
+        let (rendered, stuck) = frames(&mut vm, &names, None).unwrap();
+        assert_eq!(rendered.len(), 2);
+        assert!(rendered[0].contains("label=\"interaction 0\""));
+        assert!(rendered[1].contains("  a10 [label=\"B\", style=\"rounded,filled\", fillcolor=lightpink];\n"));
+        // Pair created by the interaction has no rule
+        assert_eq!(stuck, vec![StuckPair { pair: (11, 10), atypes: (2, 2), origin: Some((1, 1)) }]);
+        assert!(vm.interaction_net.active_pairs.is_empty());
+
+        // Limit leaves the rest of the reduction to the vm
+        let mut vm = stuck_vm();
+        vm.new_rewrite((2, 2), vec![Instruction::DROP_AGENT, Instruction::DROP_AGENT]).unwrap();
+        assert_eq!(frames(&mut vm, &names, Some(1)).unwrap().0.len(), 2);
+        assert_eq!(vm.interaction_net.active_pairs.len(), 1);
+    }
+
+    #[test]
+    pub fn test_json() {
+        use crate::interaction::json::{net_from_json, net_to_json, vm_from_json, vm_to_json, JsonError};
+        use serde_json::{json, Value};
+        use std::collections::HashMap;
+
+        let names = HashMap::from([("A".to_string(), 1), ("B".to_string(), 2)]);
+        let mut net = InteractionNet::new();
+        let ids: Vec<u64> = (0..4).map(|_| net.gen_id()).collect();
+        net.new_agent(ids[0], 1, 1);
+        net.new_agent(ids[1], 2, 0);
+        net.new_agent(ids[2], 7, 0);
+        net.heap.get_mut(ids[2]).unwrap().value = u64::MAX;
+        net.bind_agents((ids[0], 0), (ids[1], 0));
+        net.bind_agents((ids[0], 1), (ids[2], 0));
+        net.heap.release(ids[3]);
+        let text = net_to_json(&net, &names);
+        let document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document["format"], "yain-net");
+        assert_eq!(document["types"], json!({"A": 1, "B": 2}));
+        assert_eq!(document["agents"][0], json!({"id": 2, "type": "A", "value": 0, "ports": [[3, 0], [4, 0]]}));
+        assert_eq!(document["agents"][2], json!({"id": 4, "type": 7, "value": u64::MAX, "ports": [[2, 1]]}));
+        assert_eq!(document["active_pairs"], json!([[2, 3]]));
+
+        // Nothing is lost, new agents get the same ids
+        let (mut loaded, types) = net_from_json(&text).unwrap();
+        assert_eq!(types, names);
+        assert_eq!(loaded.heap.values().collect::<Vec<_>>(), net.heap.values().collect::<Vec<_>>());
+        assert_eq!(loaded.active_pairs, net.active_pairs);
+        assert_eq!(loaded.gen_id(), net.gen_id());
+        assert_eq!(loaded.gen_id(), net.gen_id());
+
+        // Vm dumped in the middle of a reduction goes on like the original
+        let mut vm = stuck_vm();
+        vm.new_rewrite((2, 2), vec![Instruction::POP(0), Instruction::POP(1)]).unwrap();
+        vm.code = vec![Instruction::GEN, Instruction::POP(3)];
+        vm.interface.insert("x".to_string(), 10);
+        assert!(matches!(vm.reduce_with_limit(1), Ok(vm::Outcome::Exhausted(_))));
+        let text = vm_to_json(&vm, &names);
+        let document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document["origins"], json!([["A", "A"]]));
+        let (mut loaded, _) = vm_from_json(&text).unwrap();
+        assert_eq!(loaded.rules.iter().collect::<Vec<_>>(), vm.rules.iter().collect::<Vec<_>>());
+        assert_eq!((&loaded.code, loaded.pc, &loaded.interface), (&vm.code, vm.pc, &vm.interface));
+        assert_eq!(loaded.interaction_net.origins, vm.interaction_net.origins);
+        assert_eq!(vm_to_json(&loaded, &names), text);
+        assert_eq!(loaded.reduce(), vm.reduce());
+        assert_eq!(loaded.interaction_net.heap.values().collect::<Vec<_>>(), vm.interaction_net.heap.values().collect::<Vec<_>>());
+
+        // Pairs collected by a reduction are still wired, they become active again without origin
+        let mut vm = stuck_vm();
+        assert_eq!(vm.reduce().unwrap().len(), 1);
+        let document: Value = serde_json::from_str(&vm_to_json(&vm, &names)).unwrap();
+        assert_eq!((&document["net"]["active_pairs"], &document["origins"]), (&json!([[10, 11]]), &json!([null])));
+        let (mut loaded, _) = vm_from_json(&document.to_string()).unwrap();
+        assert_eq!(loaded.reduce().unwrap(), vec![StuckPair { pair: (10, 11), atypes: (2, 2), origin: None }]);
+
+        // Broken documents, edited as JSON values
+        let net_document: Value = serde_json::from_str(&net_to_json(&net, &names)).unwrap();
+        let edit = |document: &Value, change: &dyn Fn(&mut Value)| {
+            let mut document = document.clone();
+            change(&mut document);
+            document.to_string()
+        };
+        let schema = |path: &str| Some(JsonError::Schema { path: path.to_string() });
+        assert_eq!(net_from_json(&text).err(), Some(JsonError::Format { expected: "yain-net" }));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["version"] = json!(2))).err(), Some(JsonError::Version(2)));
+        assert!(matches!(net_from_json("{\"format\": \"yain-net\""), Err(JsonError::Syntax(_))));
+        assert!(matches!(net_from_json(&edit(&net_document, &|d| d["last_id"] = json!(-1))), Err(JsonError::Syntax(_))));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["agents"][0]["ports"][1] = json!([4, 5]))).err(),
+                   schema("agents[0].ports[1]"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["agents"][0]["type"] = json!("C"))).err(),
+                   schema("agents[0].type"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["agents"][1]["id"] = json!(2))).err(),
+                   schema("agents[1].id"));
+        // Agents above last_id, asymmetric wires, live or unknown free ids
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["last_id"] = json!(3))).err(), schema("agents[2].id"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["agents"][2]["ports"][0] = json!([3, 0]))).err(),
+                   schema("agents[0].ports[1]"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["free_ids"] = json!([4]))).err(), schema("free_ids[0]"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["free_ids"] = json!([9]))).err(), schema("free_ids[0]"));
+        // Active pairs must be connected by their principal ports
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["active_pairs"] = json!([[2, 4]]))).err(),
+                   schema("active_pairs[0]"));
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["active_pairs"] = json!([[2, 3], [3, 2]]))).err(),
+                   schema("active_pairs[1]"));
+        // and principal ports wired to each other must be an active pair
+        assert_eq!(net_from_json(&edit(&net_document, &|d| d["active_pairs"] = json!([]))).err(),
+                   schema("agents[0].ports[0]"));
+        // Ports are not wired to themselves
+        let looped = |d: &mut Value| {
+            d["agents"][0]["ports"][1] = json!([2, 1]);
+            d["agents"][2]["ports"][0] = Value::Null;
+        };
+        assert_eq!(net_from_json(&edit(&net_document, &looped)).err(), schema("agents[0].ports[1]"));
+
+        let vm_document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(vm_from_json(&edit(&vm_document, &|d| d["rules"][1]["code"][1] = json!("JUMP 1"))).err(),
+                   schema("rules[1].code[1]"));
+        assert!(matches!(vm_from_json(&edit(&vm_document, &|d| d["code"] = json!(["POP 3"]))),
+                         Err(JsonError::Verify { rule: None, error: VerifyError::Underflow { pc: 0, .. } })));
+        // Rules only for types of the type mapping
+        assert_eq!(vm_from_json(&edit(&vm_document, &|d| d["rules"][0]["left"] = json!(4000000000u64))).err(),
+                   schema("rules[0].left"));
+        assert_eq!(vm_from_json(&edit(&vm_document, &|d| d["net"]["last_id"] = json!(1))).err(),
+                   schema("net.agents[0].id"));
+        // Boundary agents are agents of the net, every active pair has an origin
+        assert_eq!(vm_from_json(&edit(&vm_document, &|d| d["interface"]["x"] = json!(99))).err(), schema("interface.x"));
+        assert_eq!(vm_from_json(&edit(&vm_document, &|d| d["origins"] = json!([]))).err(), schema("origins"));
+    }
+
+    #[test]
+    pub fn test_parallel_policies() {
+        for policy in [StuckPolicy::Collect, StuckPolicy::Keep, StuckPolicy::Error] {
+            let mut sequential = stuck_vm();
+            sequential.policy = policy;
+            let mut parallel = stuck_vm();
+            parallel.policy = policy;
+
+            assert_eq!(parallel.reduce_parallel(2), sequential.reduce());
+            assert_eq!(parallel.interaction_net.active_pairs, sequential.interaction_net.active_pairs);
+            assert_eq!(parallel.interaction_net.heap.len(), sequential.interaction_net.heap.len());
+        }
+
+        // Pairs sharing a neighbour are reduced in different rounds
+        let mut vm = VM::new();
+        vm.policy = StuckPolicy::Error;
+        vm.new_rewrite((1, 2), vec![Instruction::DROP_AGENT, Instruction::DROP_AGENT]).unwrap();
+        vm.interaction_net.new_agent(2, 1, 1);
+        vm.interaction_net.new_agent(3, 2, 0);
+        vm.interaction_net.new_agent(4, 1, 1);
+        vm.interaction_net.new_agent(5, 2, 0);
+        vm.interaction_net.new_agent(6, 0, 2);
+        vm.interaction_net.bind_agents((2, 1), (6, 1));
+        vm.interaction_net.bind_agents((4, 1), (6, 2));
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        vm.interaction_net.bind_agents((4, 0), (5, 0));
+        assert_eq!(vm.reduce_parallel(4), Ok(vec![]));
+        assert_eq!(vm.interaction_net.heap.len(), 1);
+        assert_eq!(vm.interaction_net.query_agent(6).ports, vec![FREE, FREE, FREE]);
+
+        // Rule dropping an agent behind a neighbour is not local, reduction falls back to sequential
+        let far = vec![
+            Instruction::POP(0), Instruction::POP(1),
+            Instruction::PUSH(0), Instruction::CONST(1), Instruction::PORT, Instruction::POP(3), Instruction::POP(2),
+            Instruction::PUSH(2), Instruction::CONST(1), Instruction::PORT, Instruction::POP(3), Instruction::DROP_AGENT,
+            Instruction::PUSH(0), Instruction::DROP_AGENT, Instruction::PUSH(1), Instruction::DROP_AGENT,
+        ];
+        assert!(verify_local(&[Instruction::DROP_AGENT, Instruction::DROP_AGENT]));
+        assert!(!verify_local(&far));
+        let mut vm = VM::new();
+        vm.new_rewrite((1, 2), far).unwrap();
+        vm.interaction_net.new_agent(2, 1, 1);
+        vm.interaction_net.new_agent(3, 2, 0);
+        vm.interaction_net.new_agent(6, 0, 1);
+        vm.interaction_net.new_agent(7, 0, 0);
+        vm.interaction_net.bind_agents((2, 1), (6, 0));
+        vm.interaction_net.bind_agents((6, 1), (7, 0));
+        vm.interaction_net.bind_agents((2, 0), (3, 0));
+        assert_eq!(vm.reduce_parallel(2), Ok(vec![]));
+        assert_eq!(vm.interaction_net.heap.len(), 1);
+        assert_eq!(vm.interaction_net.query_agent(6).ports, vec![FREE, FREE]);
+    }
+
+    #[test]
+    pub fn test_combinators() {
+        use crate::interaction::combinators::*;
+
+        // Boundary agent for every port of the net
+        let var = |cn: &mut Combinators, port: (u64, u64)| {
+            let id = cn.new_node(VAR);
+            cn.interaction_net.bind_agents((id, 1), port);
+            id
+        };
+
+        // Constructors annihilate crosswise, duplicators straight
+        for (atype, crossed) in [(CON, true), (DUP, false)] {
+            let mut cn = Combinators::new();
+            let (a, b) = (cn.new_node(atype), cn.new_node(atype));
+            cn.interaction_net.bind_agents((a, 0), (b, 0));
+            let vars: Vec<u64> = [(a, 1), (a, 2), (b, 1), (b, 2)].iter().map(|p| var(&mut cn, *p)).collect();
+            assert!(cn.reduce().is_empty());
+            assert_eq!(cn.interaction_net.heap.len(), 4);
+            let other = if crossed { vars[3] } else { vars[2] };
+            assert_eq!(cn.interaction_net.port((vars[0], 1)), (other, 1));
+        }
+
+        // Wire looping through both agents vanishes
+        let mut cn = Combinators::new();
+        let (a, b) = (cn.new_node(CON), cn.new_node(CON));
+        cn.interaction_net.bind_agents((a, 0), (b, 0));
+        cn.interaction_net.bind_agents((a, 1), (b, 2));
+        cn.interaction_net.bind_agents((a, 2), (b, 1));
+        assert!(cn.reduce().is_empty());
+        assert!(cn.interaction_net.heap.is_empty());
+
+        // Wire looping through one agent connects the other two ports
+        let mut cn = Combinators::new();
+        let (a, b) = (cn.new_node(DUP), cn.new_node(DUP));
+        cn.interaction_net.bind_agents((a, 0), (b, 0));
+        cn.interaction_net.bind_agents((a, 1), (a, 2));
+        let (x, y) = (var(&mut cn, (b, 1)), var(&mut cn, (b, 2)));
+        assert!(cn.reduce().is_empty());
+        assert_eq!(cn.interaction_net.port((x, 1)), (y, 1));
+
+        // Eraser spreads over whole tree
+        let mut cn = Combinators::new();
+        let (e, a, b) = (cn.new_node(ERA), cn.new_node(CON), cn.new_node(DUP));
+        cn.interaction_net.bind_agents((e, 0), (a, 0));
+        cn.interaction_net.bind_agents((a, 1), (b, 0));
+        let x = var(&mut cn, (a, 2));
+        var(&mut cn, (b, 1));
+        var(&mut cn, (b, 2));
+        assert!(cn.reduce().is_empty());
+        // Only boundary agents and their erasers are left
+        assert_eq!(cn.interaction_net.heap.len(), 6);
+        assert_eq!(cn.interaction_net.atype(cn.interaction_net.port((x, 1)).0), ERA);
+
+        // Commutation copies both agents, copies meeting the originals annihilate again
+        let mut cn = Combinators::new();
+        let (a, b) = (cn.new_node(CON), cn.new_node(DUP));
+        cn.interaction_net.bind_agents((a, 0), (b, 0));
+        let vars: Vec<u64> = [(a, 1), (a, 2), (b, 1), (b, 2)].iter().map(|p| var(&mut cn, *p)).collect();
+        assert!(cn.reduce().is_empty());
+        assert_eq!(cn.interaction_net.heap.len(), 8);
+        for v in &vars[..2] {
+            assert_eq!(cn.interaction_net.atype(cn.interaction_net.port((*v, 1)).0), DUP);
+        }
+        for v in &vars[2..] {
+            assert_eq!(cn.interaction_net.atype(cn.interaction_net.port((*v, 1)).0), CON);
+        }
+
+        // References expand to their definition, references to nothing get stuck
+        let mut cn = Combinators::new();
+        let mut t = Template::new();
+        let node = t.node(CON);
+        t.connect((node, 0), FREE);
+        t.connect((node, 1), (node, 2));
+        let defined = cn.define(Some(t));
+        let undefined = cn.define(None);
+        let (r, s) = (cn.new_node(defined), cn.new_node(undefined));
+        cn.interaction_net.bind_agents((r, 0), (s, 0));
+        let stuck = cn.reduce();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].atypes.1, undefined);
+        assert_eq!(cn.interaction_net.atype(stuck[0].pair.0), CON);
     }
-}
\ No newline at end of file
+}
@@ -1,15 +1,39 @@
-#[path = "../src/interaction/mod.rs"]
-mod interaction;
-
-#[path = "../src/parser/mod.rs"]
-mod parser;
+use YAIN::{interaction, parser};
 
 #[cfg(test)]
 mod unit_tests {
 
-    use crate::parser::{ast, AstNode, Semantic};
+    use crate::parser::{ast, AstNode, CompileError, ParseError, Semantic, Span};
     use crate::parser::Semantic::PrincipalType;
-    use crate::parser::transform::compileProgram;
+    use crate::parser::check::{check, infer_arities, Violation};
+    use crate::parser::transform::{compileProgram, rule_equation, INTERFACE};
+    use crate::interaction::InteractionNet;
+    use crate::interaction::verify::verify_local;
+    use crate::parser::readback::{readback, readback_interface, readback_port, roots};
+    use crate::parser::Semantic::AgentType;
+    use std::collections::HashMap;
+
+    // Reset all spans of tree
+    fn strip(node: AstNode) -> AstNode {
+        let d = Span::default();
+        match node {
+            AstNode::Id(s, _) => AstNode::Id(s, d),
+            AstNode::Var(s, _) => AstNode::Var(s, d),
+            AstNode::Num(n, _) => AstNode::Num(n, d),
+            AstNode::Cons(s, _) => AstNode::Cons(s, d),
+            AstNode::Agent(id, ports, _) =>
+                AstNode::Agent(Box::new(strip(*id)), ports.into_iter().map(strip).collect(), d),
+            AstNode::Principal(left, right, _) =>
+                AstNode::Principal(Box::new(strip(*left)), Box::new(strip(*right)), d),
+            AstNode::Equation(left, right, principals, _) =>
+                AstNode::Equation(Box::new(strip(*left)), Box::new(strip(*right)),
+                                  principals.into_iter().map(strip).collect(), d),
+            AstNode::Signature(id, arity, _) => AstNode::Signature(Box::new(strip(*id)), arity, d),
+            AstNode::Term(principals, _) => AstNode::Term(principals.into_iter().map(strip).collect(), d),
+            AstNode::Program(equations, term, _) =>
+                AstNode::Program(equations.into_iter().map(strip).collect(), Box::new(strip(*term)), d),
+        }
+    }
 
     #[test]
     pub fn test_ast() {
@@ -18,44 +42,67 @@ mod unit_tests {
         INC(x) # O() = x
         INC(O) ~ S(O)".to_string();
 
-        let an = ast(&s);
+        let an = ast(&s).unwrap();
+
+        // Spans are checked separately
+        let d = Span::default();
 
-        assert_eq!(an,
+        assert_eq!(strip(an),
          AstNode::Program(
              vec![
                 AstNode::Equation(
                     Box::new(AstNode::Agent(
-                                Box::new(AstNode::Id("INC".to_string())),
-                                vec![AstNode::Var("x".to_string())])),
+                                Box::new(AstNode::Id("INC".to_string(), d)),
+                                vec![AstNode::Var("x".to_string(), d)], d)),
                     Box::new(AstNode::Agent(
-                                Box::new(AstNode::Id("S".to_string())),
-                                vec![AstNode::Var("y".to_string())])),
+                                Box::new(AstNode::Id("S".to_string(), d)),
+                                vec![AstNode::Var("y".to_string(), d)], d)),
                     vec![
                         AstNode::Principal(
                             Box::new(AstNode::Agent(
-                                Box::new(AstNode::Id("S".to_string())),
-                                vec![])),
+                                Box::new(AstNode::Id("S".to_string(), d)),
+                                vec![], d)),
                             Box::new(AstNode::Agent(
-                                Box::new(AstNode::Id("S".to_string())),
-                                vec![AstNode::Var("y".to_string())])))]),
+                                Box::new(AstNode::Id("S".to_string(), d)),
+                                vec![AstNode::Var("y".to_string(), d)], d)), d)], d),
 
                 AstNode::Equation(
                     Box::new(AstNode::Agent(
-                        Box::new(AstNode::Id("INC".to_string())),
-                        vec![AstNode::Var("x".to_string())])),
-                    Box::new(AstNode::Agent(Box::new(AstNode::Id("O".to_string())), vec![])),
-                    vec![AstNode::Var("x".to_string())])
+                        Box::new(AstNode::Id("INC".to_string(), d)),
+                        vec![AstNode::Var("x".to_string(), d)], d)),
+                    Box::new(AstNode::Agent(Box::new(AstNode::Id("O".to_string(), d)), vec![], d)),
+                    vec![AstNode::Var("x".to_string(), d)], d)
              ],
-             Box::new(AstNode::Principal(
+             Box::new(AstNode::Term(vec![AstNode::Principal(
                  Box::new(
-                                AstNode::Agent(Box::new(AstNode::Id("INC".to_string())),
-                                               vec![AstNode::Cons("O".to_string())])),
+                                AstNode::Agent(Box::new(AstNode::Id("INC".to_string(), d)),
+                                               vec![AstNode::Cons("O".to_string(), d)], d)),
                  Box::new(
-                                AstNode::Agent(Box::new(AstNode::Id("S".to_string())),
-                                               vec![AstNode::Cons("O".to_string())])))))
+                                AstNode::Agent(Box::new(AstNode::Id("S".to_string(), d)),
+                                               vec![AstNode::Cons("O".to_string(), d)], d)), d)], d)), d)
         );
     }
 
+    #[test]
+    pub fn test_spans() {
+
+        let s = "
+        INC(x) # S(y) = S() ~ S(y)
+        INC(x) # O() = x
+        INC(O) ~ S(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+
+        let eq = &pg.equations[1];
+        assert_eq!((eq.span.line, eq.span.col), (3, 9));
+        assert_eq!(&s[eq.span.start..eq.span.end], "INC(x) # O() = x");
+        assert_eq!(&s[eq.right.span.start..eq.right.span.end], "O()");
+
+        let var = &pg.equations[0].principals[0].right.ports[0];
+        assert_eq!((var.span.line, var.span.col), (2, 33));
+        assert_eq!(&s[var.span.start..var.span.end], "y");
+    }
+
     #[test]
     pub fn test_semantic() {
 
@@ -64,9 +111,9 @@ mod unit_tests {
         INC(x) # O() = x
         INC(O) ~ S(O)".to_string();
 
-        let an = ast(&s);
+        let an = ast(&s).unwrap();
 
-        let pg = Semantic::Program::fromAst(an.clone());
+        let pg = Semantic::Program::fromAst(an.clone()).unwrap();
 
         assert_eq!(pg.equations.len(), 2);
         assert_eq!(pg.equations[0].left.name, "INC".to_string());
@@ -82,13 +129,13 @@ mod unit_tests {
     pub fn test_compilation() {
 
         let s = "
-        INC(x) # S(y) = S() ~ S(y)
+        INC(x) # S(y) = S(x) ~ S(y)
         INC(x) # O() = x
         INC(O) ~ S(O)".to_string();
 
-        let an = ast(&s);
-        let pg = Semantic::Program::fromAst(an.clone());
-        let tup = compileProgram(pg);
+        let an = ast(&s).unwrap();
+        let pg = Semantic::Program::fromAst(an.clone()).unwrap();
+        let tup = compileProgram(pg).unwrap();
         let mut vm = tup.0;
         let tm = tup.1;
 
@@ -96,9 +143,811 @@ mod unit_tests {
         assert_eq!(*tm.get("S").unwrap(), 1);
         assert_eq!(*tm.get("O").unwrap(), 2);
 
-        vm.reduce();
+        vm.reduce().unwrap();
 
         assert_eq!(vm.interaction_net.heap.len(), 4);
-        assert_eq!(vm.rules.len(), 2);
+        // Every rule is also registered for the mirrored pair
+        assert_eq!(vm.rules.len(), 4);
+    }
+
+    #[test]
+    pub fn test_parse_error() {
+
+        let s = "
+        INC(x) # S(y = S() ~ S(y)
+        INC(O) ~ S(O)".to_string();
+
+        match ast(&s) {
+            Err(ParseError::Syntax { span, expected, snippet, .. }) => {
+                assert_eq!(span.line, 2);
+                assert_eq!(span.col, 18);
+                assert!(!expected.is_empty());
+                assert!(snippet.contains("INC(x) # S(y = S() ~ S(y)"));
+                assert!(snippet.ends_with(&format!("{}^", " ".repeat(17))));
+            }
+            other => panic!("Expected syntax error, got {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    pub fn test_linearity() {
+
+        let s = "
+        INC(x) # S(y) = S(z) ~ S(y)
+        INC(x) # O() = x, S(x) ~ O
+        INC(O) ~ S(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+
+        match compileProgram(pg) {
+            Err(CompileError::Invalid(violations)) => {
+                let found: Vec<(usize, usize)> = violations.iter()
+                    .map(|v| (v.span().line, v.span().col))
+                    .collect();
+                assert_eq!(found.len(), 3);
+                assert!(matches!(&violations[0], Violation::Unused { name, .. } if name == "x"));
+                assert!(matches!(&violations[1], Violation::Unbound { name, .. } if name == "z"));
+                assert!(matches!(&violations[2], Violation::Repeated { name, spans } if name == "x" && spans.len() == 2));
+                assert_eq!(found, vec![(2, 13), (2, 27), (3, 24)]);
+            }
+            other => panic!("Expected violations, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    pub fn test_arity() {
+
+        let s = "
+        agent S/1
+        agent O/0
+        INC(x) # S(y) = S() ~ S(y)
+        INC(x) # O() = INC(x, x) ~ O
+        INC(O) ~ S(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+
+        assert_eq!(pg.signatures.len(), 2);
+        assert_eq!(pg.signatures[0].name, "S".to_string());
+        assert_eq!(pg.signatures[0].arity, 1);
+
+        let (arities, violations) = infer_arities(&pg);
+        assert_eq!(*arities.get("INC").unwrap(), 1);
+        assert_eq!(*arities.get("S").unwrap(), 1);
+        assert_eq!(*arities.get("O").unwrap(), 0);
+
+        // S() conflicts with declaration, INC(x, x) with first use of INC
+        assert_eq!(violations.len(), 2);
+        assert!(matches!(&violations[0],
+            Violation::Arity { name, expected: 1, found: 0, origin, .. } if name == "S" && origin.line == 2));
+        assert!(matches!(&violations[1],
+            Violation::Arity { name, expected: 1, found: 2, origin, .. } if name == "INC" && origin.line == 4));
+
+        assert!(matches!(compileProgram(pg), Err(CompileError::Invalid(_))));
+    }
+
+    #[test]
+    pub fn test_symmetric_rules() {
+
+        let s = "
+        ADD(x) # S(y) = ADD(S(x)) ~ y
+        O() # ADD(x) = x
+        S(O) ~ ADD(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+
+        let add = *tm.get("ADD").unwrap();
+        let s_type = *tm.get("S").unwrap();
+        assert!(vm.rules.contains_key(&(add, s_type)));
+        assert!(vm.rules.contains_key(&(s_type, add)));
+
+        // S ~ ADD fires ADD # S, then ADD ~ O fires O # ADD
+        vm.reduce().unwrap();
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+        assert!(vm.interaction_net.active_pairs.is_empty());
+    }
+
+    #[test]
+    pub fn test_conflicting_rules() {
+
+        // Same rule written the other way round is fine
+        let s = "
+        A(x) # B(y) = x ~ y
+        B(u) # A(v) = u ~ v
+        A(O) ~ B(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        assert_eq!(compileProgram(pg).unwrap().0.rules.len(), 2);
+
+        // Different results for the same pair are rejected
+        let s = "
+        A(x) # B(y) = x ~ y
+        B(u) # A(v) = u, O ~ v
+        A(O) ~ B(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        match compileProgram(pg) {
+            Err(CompileError::Invalid(violations)) => {
+                assert_eq!(violations.len(), 1);
+                assert!(matches!(&violations[0],
+                    Violation::Conflict { left, right, span, origin }
+                    if left == "B" && right == "A" && span.line == 3 && origin.line == 2));
+            }
+            other => panic!("Expected conflict, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    pub fn test_stuck_origin() {
+
+        let s = "
+        A(x) # B(y) = C(x) ~ D(y)
+        A(O) ~ B(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg.clone()).unwrap();
+
+        let stuck = vm.reduce().unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].atypes, (*tm.get("C").unwrap(), *tm.get("D").unwrap()));
+
+        // Pair was created by the only rule
+        let equation = rule_equation(&pg, &tm, stuck[0].origin.unwrap()).unwrap();
+        assert_eq!(&s[equation.span.start..equation.span.end], "A(x) # B(y) = C(x) ~ D(y)");
+    }
+
+    #[test]
+    pub fn test_port_order() {
+
+        let s = "
+        SWAP(x, y) # GO() = PAIR(y, x) ~ DONE()
+        SWAP(A(), B()) ~ GO()".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+
+        // Only PAIR # DONE is left
+        let stuck = vm.reduce().unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(vm.interaction_net.heap.len(), 4);
+
+        // Ports of SWAP arrive swapped at PAIR
+        let net = &vm.interaction_net;
+        let pair = net.heap.values().find(|a| a.atype == *tm.get("PAIR").unwrap()).unwrap();
+        assert_eq!(pair.ports.len(), 3);
+        assert_eq!(net.atype(pair.ports[1].0), *tm.get("B").unwrap());
+        assert_eq!(net.atype(pair.ports[2].0), *tm.get("A").unwrap());
+        assert_eq!((pair.ports[1].1, pair.ports[2].1), (0, 0));
+        // Wires point back to PAIR
+        assert_eq!(net.port(pair.ports[1]), (pair.id, 1));
+        assert_eq!(net.port(pair.ports[2]), (pair.id, 2));
+
+        // Neighbours of the active pair get connected directly
+        let s = "
+        FWD(x, y) # GO() = x ~ y
+        FWD(A(), B()) ~ GO()".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+
+        let stuck = vm.reduce().unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].atypes, (*tm.get("B").unwrap(), *tm.get("A").unwrap()));
+        assert_eq!(vm.interaction_net.heap.len(), 2);
+    }
+
+    // Agents with the types and ports of their neighbours, independent of ids
+    fn shape(net: &InteractionNet) -> Vec<(u64, Vec<(u64, u64)>)> {
+        let mut shape: Vec<(u64, Vec<(u64, u64)>)> = net.heap.values().map(|agent| {
+            let ports = agent.ports.iter()
+                .map(|p| if p.0 == 0 { (u64::MAX, 0) } else { (net.atype(p.0), p.1) })
+                .collect();
+            (agent.atype, ports)
+        }).collect();
+        shape.sort();
+        shape
+    }
+
+    #[test]
+    pub fn test_parallel() {
+
+        // Binary tree whose leaves carry a number
+        fn tree(depth: usize) -> String {
+            if depth == 0 {
+                "L(S(S(O)))".to_string()
+            } else {
+                format!("N({}, {})", tree(depth - 1), tree(depth - 1))
+            }
+        }
+
+        let s = format!("
+        E() # N(l, r) = E() ~ l, E() ~ r
+        E() # L(x) = P(x) ~ Q()
+        E() ~ {}", tree(6));
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+
+        let (mut sequential, _) = compileProgram(pg.clone()).unwrap();
+        let expected = sequential.reduce().unwrap();
+        assert_eq!(expected.len(), 64);
+
+        for workers in [1, 2, 4] {
+            let (mut vm, _) = compileProgram(pg.clone()).unwrap();
+            // Compiled rules stay within their footprint, so reduction really runs in parallel
+            assert!(vm.rules.iter().all(|(_, code)| verify_local(code)));
+            let stuck = vm.reduce_parallel(workers).unwrap();
+            assert_eq!(stuck.len(), expected.len());
+            assert_eq!(vm.interaction_net.heap.len(), sequential.interaction_net.heap.len());
+            assert_eq!(shape(&vm.interaction_net), shape(&sequential.interaction_net));
+        }
+    }
+
+    #[test]
+    pub fn test_readback() {
+
+        let s = "
+        ADD(x) # S(y) = ADD(S(x)) ~ y
+        ADD(x) # O() = x
+        ADD(O) ~ S(S(O))".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert!(vm.reduce().unwrap().is_empty());
+
+        let roots = roots(&vm.interaction_net);
+        assert_eq!(roots.len(), 1);
+        let term = readback(&vm.interaction_net, &tm, roots[0]);
+        assert_eq!(term.to_string(), "S(S(O))");
+        assert_eq!(term.ports[0].atype, AgentType::Agent);
+
+        // Nets which are no trees
+        let tm: HashMap<String, u64> = [("P".to_string(), 0), ("A".to_string(), 1)].into_iter().collect();
+        let mut net = InteractionNet::new();
+        // Wire between two auxiliary ports of the same agent
+        net.new_agent(2, 0, 2);
+        net.bind_agents((2, 1), (2, 2));
+        assert_eq!(readback(&net, &tm, 2).to_string(), "P(a, a)");
+        // Auxiliary port connected back to own principal port
+        net.new_agent(3, 1, 1);
+        net.bind_agents((3, 1), (3, 0));
+        assert_eq!(readback(&net, &tm, 3).to_string(), "A(a)");
+        // Unconnected ports and unknown types
+        net.new_agent(4, 0, 2);
+        net.new_agent(5, 7, 0);
+        net.bind_agents((4, 2), (5, 0));
+        assert_eq!(readback(&net, &tm, 4).to_string(), "P(a, #7)");
+        assert_eq!(readback_port(&net, &tm, (4, 2)).to_string(), "#7");
+        assert_eq!(readback_port(&net, &tm, (4, 1)).to_string(), "a");
+
+        // Long chains do not overflow the stack
+        let depth = 100000;
+        let tm: HashMap<String, u64> = [("O".to_string(), 0), ("S".to_string(), 1)].into_iter().collect();
+        let mut net = InteractionNet::new();
+        for id in 2..depth + 2 {
+            net.new_agent(id, 1, 1);
+            if id > 2 {
+                net.bind_agents((id - 1, 1), (id, 0));
+            }
+        }
+        net.new_agent(depth + 2, 0, 0);
+        net.bind_agents((depth + 1, 1), (depth + 2, 0));
+        let mut term = readback(&net, &tm, 2);
+        let mut length = 0;
+        while let Some(inner) = term.ports.pop() {
+            assert_eq!(term.name, "S");
+            term = inner;
+            length += 1;
+        }
+        assert_eq!((term.name.as_str(), length), ("O", depth));
+    }
+
+    #[test]
+    pub fn test_interface() {
+
+        let s = "
+        ADD(x) # S(y) = ADD(S(x)) ~ y
+        ADD(x) # O() = x
+        ADD(O) ~ S(r)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert_eq!(vm.interface.len(), 1);
+        assert!(tm.contains_key(INTERFACE));
+        // Boundary agent is no active pair
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "ADD(S(O))");
+        assert!(readback_interface(&vm, &tm, "q").is_none());
+
+        // Interface variables connected to each other, variable occurring twice is a wire
+        let s = "
+        A(x, r) ~ B(x, s)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (vm, tm) = compileProgram(pg).unwrap();
+        assert_eq!(vm.interface.len(), 2);
+        let a = vm.interaction_net.heap.values().find(|a| a.atype == *tm.get("A").unwrap()).unwrap();
+        let b = vm.interaction_net.heap.values().find(|a| a.atype == *tm.get("B").unwrap()).unwrap();
+        assert_eq!(a.ports[1], (b.id, 1));
+        assert_eq!(a.ports[2], (*vm.interface.get("r").unwrap(), 1));
+        // Auxiliary port can only be shown as a wire
+        assert_eq!(readback_interface(&vm, &tm, "s").unwrap().atype, AgentType::Var);
+
+        let s = "
+        r ~ s".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (vm, tm) = compileProgram(pg).unwrap();
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "s");
+
+        // A wire has only two ends
+        let s = "
+        A(x, x) ~ B(x)".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        match compileProgram(pg) {
+            Err(CompileError::Invalid(violations)) => {
+                assert!(matches!(&violations[0], Violation::Repeated { name, spans } if name == "x" && spans.len() == 3));
+            }
+            other => panic!("expected violation, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    pub fn test_multiple_terms() {
+
+        let s = "
+        ADD(x) # S(y) = ADD(S(x)) ~ y
+        ADD(x) # O() = x
+        ADD(O) ~ S(r),
+        ADD(S(O)) ~ S(q)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        assert_eq!(pg.terms.len(), 2);
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        // Both connections are active pairs
+        assert_eq!(vm.interaction_net.active_pairs.len(), 2);
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "ADD(S(O))");
+        assert_eq!(readback_interface(&vm, &tm, "q").unwrap().to_string(), "ADD(S(S(O)))");
+
+        // Connections share variables
+        let s = "
+        A(x) ~ B(), C(x) ~ D(),
+        y ~ E(y)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert!(vm.interface.is_empty());
+        assert_eq!(vm.reduce().unwrap().len(), 2);
+        let find = |name: &str| vm.interaction_net.heap.values().find(|a| a.atype == *tm.get(name).unwrap()).unwrap();
+        assert_eq!(find("A").ports[1], (find("C").id, 1));
+        // Principal port of E is connected to its own auxiliary port
+        assert_eq!(find("E").ports[0], (find("E").id, 1));
+
+        // Term has more boundary agents and wires than fit into the scratchpad
+        let name = |i: u8| format!("{}{}", (b'a' + i / 26) as char, (b'a' + i % 26) as char);
+        let terms: Vec<String> = (0..150).map(|i| format!("v{} ~ S(O), ADD(O) ~ S(w{})", name(i), name(i))).collect();
+        let s = format!("
+        ADD(x) # S(y) = ADD(S(x)) ~ y
+        ADD(x) # O() = x
+        {}", terms.join(",\n"));
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert_eq!(vm.interface.len(), 300);
+        assert_eq!(vm.interaction_net.active_pairs.len(), 150);
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "vaa").unwrap().to_string(), "S(O)");
+        assert_eq!(readback_interface(&vm, &tm, "vft").unwrap().to_string(), "S(O)");
+        assert_eq!(readback_interface(&vm, &tm, "wft").unwrap().to_string(), "ADD(S(O))");
+    }
+
+    #[test]
+    pub fn test_builtins() {
+
+        let s = "
+        DUP(p, q) ~ S(S(O)),
+        ERA ~ S(PAIR(O, O)),
+        DUP(r, s) ~ DUP(t, u)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert!(vm.reduce().unwrap().is_empty());
+
+        // Duplicated
+        assert_eq!(readback_interface(&vm, &tm, "p").unwrap().to_string(), "S(S(O))");
+        assert_eq!(readback_interface(&vm, &tm, "q").unwrap().to_string(), "S(S(O))");
+        // Annihilated
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "t");
+        assert_eq!(readback_interface(&vm, &tm, "s").unwrap().to_string(), "u");
+        // Erased, only the two copies and the boundary agents are left
+        assert_eq!(vm.interaction_net.heap.len(), 6 + 6);
+
+        // Rules of the user take precedence
+        let s = "
+        ERA() # S(x) = K() ~ x
+        ERA ~ S(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        let stuck = vm.reduce().unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].atypes, (*tm.get("K").unwrap(), *tm.get("O").unwrap()));
+
+        // Built-in agents have a fixed arity
+        let s = "
+        ERA(x) ~ S(O)".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        match compileProgram(pg) {
+            Err(CompileError::Invalid(violations)) => {
+                assert!(matches!(&violations[0], Violation::Arity { name, expected: 0, found: 1, .. } if name == "ERA"));
+                assert!(violations[0].to_string().contains("built in"));
+            }
+            other => panic!("expected violation, got {:?}", other.map(|_| ())),
+        }
+
+        // Wires inside the result need exactly two ends
+        let s = "
+        A(x) # B() = C(y) ~ x, D(y) ~ E()
+        A(x) # F() = G(y) ~ x, H(y) ~ K(y)
+        A(r) ~ B()".to_string();
+
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        assert_eq!(check(&pg).len(), 1);
+        assert!(matches!(&check(&pg)[0], Violation::Repeated { name, spans } if name == "y" && spans.len() == 3));
+        let pg = Semantic::Program { equations: pg.equations[..1].to_vec(), ..pg };
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert_eq!(vm.reduce().unwrap().len(), 1);
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "C(a)");
+    }
+
+    #[test]
+    pub fn test_combinators() {
+        use crate::parser::encode::{compile_combinators, decode};
+
+        // Results of the vm and of the combinators
+        let run = |s: &str| {
+            let pg = Semantic::Program::fromAst(ast(s).unwrap()).unwrap();
+            let (mut vm, tm) = compileProgram(pg.clone()).unwrap();
+            assert!(vm.reduce().unwrap().is_empty());
+            let (mut cn, encoding) = compile_combinators(pg).unwrap();
+            assert!(cn.reduce().is_empty());
+            let decoded = decode(&cn, &encoding);
+            let mut names: Vec<&String> = vm.interface.keys().collect();
+            names.sort();
+            names.iter().map(|name| {
+                let expected = readback_interface(&vm, &tm, name).unwrap().to_string();
+                let found = readback_interface(&decoded, &encoding.type_mapping, name).unwrap().to_string();
+                assert_eq!(expected, found, "{} differs", name);
+                found
+            }).collect::<Vec<_>>()
+        };
+
+        // Rules between different agents
+        assert_eq!(run("
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(S(O)), r) ~ S(S(S(O)))"), vec!["S(S(S(S(S(O)))))"]);
+        assert_eq!(run("
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        MUL(y, r) # S(x) = DUP(a, b) ~ y, MUL(b, q) ~ x, ADD(a, r) ~ q
+        MUL(y, r) # O() = ERA ~ y, r ~ O
+        MUL(S(S(O)), r) ~ S(S(S(O)))"), vec!["S(S(S(S(S(S(O))))))"]);
+
+        // Agents of the same type build half of the result each
+        assert_eq!(run("
+        DUP(p, q) ~ S(S(O)),
+        ERA ~ S(PAIR(O, O)),
+        DUP(r, s) ~ DUP(t, u)"), vec!["S(S(O))", "S(S(O))", "t", "u", "r", "s"]);
+        assert_eq!(run("
+        A(x, u) # A(y, v) = x ~ C(u), y ~ C(v)
+        A(p, O) ~ A(q, S(O))"), vec!["C(O)", "C(S(O))"]);
+
+        // Result of agents of the same type has to be symmetric
+        let s = "
+        A(x) # A(y) = x ~ B(y)
+        A(p) ~ A(q)".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        assert!(matches!(compile_combinators(pg), Err(CompileError::Asymmetric { span }) if span.line == 2));
+
+        // Pairs without rule get stuck
+        let s = "
+        K() ~ O()".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut cn, _) = compile_combinators(pg).unwrap();
+        assert_eq!(cn.reduce().len(), 2);
+    }
+    #[test]
+    pub fn test_bytecode() {
+        use crate::interaction::bytecode::{from_bytes, to_bytes};
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        MUL(y, r) # S(x) = DUP(a, b) ~ y, MUL(b, q) ~ x, ADD(a, r) ~ q
+        MUL(y, r) # O() = ERA ~ y, r ~ O
+        MUL(S(S(O)), r) ~ S(S(S(O))),
+        *(6, n) ~ 7".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (compiled, tm) = compileProgram(pg).unwrap();
+        let bytes = to_bytes(&compiled, &tm);
+
+        // Loaded program reduces like the compiled one
+        let (mut vm, types) = from_bytes(&bytes).unwrap();
+        assert_eq!(types, tm);
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &types, "r").unwrap().to_string(), "S(S(S(S(S(S(O))))))");
+        assert_eq!(readback_interface(&vm, &types, "n").unwrap().to_string(), "42");
+
+        // Writing is deterministic and survives a round trip
+        assert_eq!(to_bytes(&vm, &types), bytes);
+    }
+
+    #[test]
+    pub fn test_assembly() {
+        use crate::interaction::assembly::{assemble, disassemble};
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(S(O)), r) ~ S(S(S(O))),
+        +(1, n) ~ 2".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (compiled, tm) = compileProgram(pg.clone()).unwrap();
+
+        // Compiled rules and code survive a round trip through assembly
+        let listing = disassemble(&compiled, &tm);
+        assert!(listing.contains("ADD # S:\n    POP 0\n    POP 1\n"));
+        assert!(listing.contains("OPERATE +"));
+        let assembly = assemble(&listing, &tm).unwrap();
+        assert_eq!(assembly.rules.len(), compiled.rules.len());
+        for (atypes, code) in &assembly.rules {
+            assert_eq!(compiled.rules.get(atypes), Some(&code[..]));
+        }
+        assert_eq!(assembly.code.as_ref(), Some(&compiled.code));
+
+        // Hand-written rule replaces the compiled one, ADD(x, r) # O() now drops the pair only
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        let patch = "
+        ADD # O:
+            POP 0
+            POP 1
+            PUSH 0
+            DROP_AGENT
+            PUSH 1
+            DROP_AGENT";
+        assemble(patch, &tm).unwrap().apply(&mut vm).unwrap();
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "S(S(S(a)))");
+        assert_eq!(readback_interface(&vm, &tm, "n").unwrap().to_string(), "3");
+    }
+
+    #[test]
+    pub fn test_lambda() {
+        use crate::parser::lambda::{compile_lambda, lambda, readback_lambda};
+
+        // Normal form of a lambda term
+        let normalize = |s: &str| {
+            let term = lambda(s).unwrap();
+            let (mut vm, tm) = compile_lambda(&term).unwrap();
+            assert!(vm.reduce().unwrap().is_empty());
+            readback_lambda(&vm, &tm).unwrap().to_string()
+        };
+
+        assert_eq!(normalize("(\\x. x) (\\y. y)"), "\\a. a");
+        assert_eq!(normalize("λx. x x"), "\\a. a a");
+        assert_eq!(normalize("\\x. f x"), "\\a. f a");
+        assert_eq!(normalize("\\f. \\x. x"), "\\a. \\b. b");
+        assert_eq!(normalize("let id = \\x. x in id id"), "\\a. a");
+
+        // Church numerals, two to the power of two
+        let two = "(\\f. \\x. f (f x))";
+        assert_eq!(normalize(&format!("{} {}", two, two)), "\\a. \\b. a (a (a (a b)))");
+        assert_eq!(normalize(&format!("{} {} f", two, two)), "\\a. f (f (f (f a)))");
+
+        // Terms too large for initial code, forty times three
+        let numeral = |n: usize, f: &str, x: &str| format!("{}{} {}{}", format!("{} (", f).repeat(n - 1), f, x, ")".repeat(n - 1));
+        let forty = format!("(\\f. \\x. {})", numeral(40, "f", "x"));
+        let three = "(\\f. \\x. f (f (f x)))";
+        assert_eq!(normalize(&format!("{} g", forty)), format!("\\a. {}", numeral(40, "g", "a")));
+        assert_eq!(normalize(&format!("(\\m. \\n. \\f. m (n f)) {} {}", forty, three)),
+                   format!("\\a. \\b. {}", numeral(120, "a", "b")));
+
+        // Keywords are no variables
+        assert!(lambda("\\x. in").is_err());
+        assert!(lambda("let x = y x").is_err());
+    }
+
+    #[test]
+    pub fn test_numbers() {
+        use crate::parser::encode::compile_combinators;
+
+        // Results of the interface in order of names
+        let run = |s: &str| {
+            let pg = Semantic::Program::fromAst(ast(s).unwrap()).unwrap();
+            let (mut vm, tm) = compileProgram(pg).unwrap();
+            assert!(vm.reduce().unwrap().is_empty());
+            let mut names: Vec<&String> = vm.interface.keys().collect();
+            names.sort();
+            names.iter().map(|name| readback_interface(&vm, &tm, name).unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // Operators compute r = x op y for OP(y, r) ~ x
+        assert_eq!(run("
+        +(3, r) ~ 4,
+        -(3, s) ~ 10,
+        -(10, t) ~ 3,
+        *(6, u) ~ 7,
+        <(2, v) ~ 1,
+        ==(2, w) ~ 3"), vec!["7", "7", "0", "42", "1", "0"]);
+
+        // Operands can be results of other operators, (2 + 3) * (4 + 4)
+        assert_eq!(run("
+        +(3, a) ~ 2,
+        +(4, b) ~ 4,
+        *(b, r) ~ a"), vec!["40"]);
+
+        // Built-in agents copy and erase numbers and waiting operators
+        assert_eq!(run("
+        DUP(a, b) ~ 5,
+        +(a, r) ~ b,
+        +(c, s) ~ 5,
+        DUP(t, u) ~ c,
+        t ~ 2, u ~ 3,
+        ERA ~ 9,
+        ERA ~ +(1, e)"), vec!["ERA", "10", "DUP(7, 8)"]);
+
+        // Rules of the program mix with numbers
+        assert_eq!(run("
+        LEN(r) # S(x) = LEN(q) ~ x, +(1, r) ~ q
+        LEN(r) # O() = r ~ 0
+        LEN(n) ~ S(S(S(O)))"), vec!["3"]);
+
+        // Literals have to fit into 64 bits
+        assert!(matches!(ast("\n r ~ 18446744073709551616"), Err(ParseError::Malformed { .. })));
+        assert_eq!(run("\n r ~ 18446744073709551615"), vec!["18446744073709551615"]);
+
+        // Rules of the program replace the ones computed by the vm, numbers can only be written into
+        // rules by building the program directly
+        let mut pg = Semantic::Program::fromAst(ast("\n +(3, r) ~ 5").unwrap()).unwrap();
+        let number = pg.terms[0].right.clone();
+        let var = |name: &str| Semantic::Agent { name: name.to_string(), atype: AgentType::Var, ports: vec![], span: Span::default() };
+        let operator = Semantic::Agent { ports: vec![var("y"), var("r")], ..pg.terms[0].left.clone() };
+        pg.equations.push(Semantic::Equation {
+            left: operator,
+            right: number,
+            principals: vec![Semantic::Principal { ptype: PrincipalType::Pure, left: var("r"), right: var("y"), span: Span::default() }],
+            span: Span::default(),
+        });
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        assert!(vm.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "3");
+
+        // Values do not survive an encoding as combinators
+        let s = "
+        A(x) # B() = x ~ 1
+        A(r) ~ B".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        assert!(matches!(compile_combinators(pg), Err(CompileError::Native { span }) if span.line == 2));
+    }
+
+    #[test]
+    pub fn test_stats() {
+        use crate::interaction::vm::{Instruction, Operator};
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(S(O)), r) ~ S(S(S(O))),
+        +(1, n) ~ 2".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        vm.profile = true;
+        assert!(vm.reduce().unwrap().is_empty());
+
+        // Rules are named after the type mapping
+        let stats = &vm.stats;
+        let rules = stats.per_rule(&tm);
+        let add = |name: &str| rules.iter().find(|(a, b, _)| a == "ADD" && b == name).unwrap().2;
+        assert_eq!(add("S").interactions, 3);
+        assert_eq!(add("O").interactions, 1);
+        assert_eq!(stats.interactions, 6);
+        assert_eq!(rules.iter().map(|(_, _, rule)| rule.instructions).sum::<u64>(), stats.executed());
+        assert_eq!(stats.count(Instruction::OPERATE(Operator::Add)), 1);
+        assert_eq!(stats.peak_active_pairs, 2);
+        let table = stats.table(&tm);
+        assert!(table.contains("ADD # S"));
+        assert!(table.contains("OPERATE"));
+    }
+
+    #[test]
+    pub fn test_debugger() {
+        use crate::interaction::debug::Debugger;
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(O), r) ~ S(S(O))".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+
+        // Breakpoints name agents of the program
+        let mut debugger = Debugger::new(&mut vm, &tm);
+        assert_eq!(debugger.command("break O # ADD").unwrap(), "breakpoint 0: O # ADD\n");
+        assert_eq!(debugger.command("break new S").unwrap(), "breakpoint 1: new S\n");
+        assert!(debugger.command("continue").unwrap().starts_with("breakpoint 1: new S\nADD # S on pair"));
+        // Second interaction of ADD and S creates an S as well
+        assert!(debugger.command("continue").unwrap().starts_with("breakpoint 1: new S\nADD # S on pair"));
+        assert!(debugger.command("continue").unwrap().starts_with("breakpoint 0: O # ADD\nADD # O on pair"));
+        assert!(debugger.command("pair").unwrap().contains(" ADD\n  port 0 -> "));
+        assert_eq!(debugger.command("continue").unwrap(), "net is normal\n");
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "S(S(S(O)))");
+    }
+
+    #[test]
+    pub fn test_dot() {
+        use crate::interaction::dot::frames;
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(O), r) ~ S(S(O))".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+
+        // Agents are named after the program
+        let (dot, stuck) = frames(&mut vm, &tm, None).unwrap();
+        assert_eq!(dot.len(), 4);
+        assert!(dot[0].contains("[label=\"ADD\", style=\"rounded,filled\""));
+        assert!(dot[0].contains("color=red"));
+        assert!(!dot[3].contains("color=red"));
+        assert!(stuck.is_empty());
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "S(S(S(O)))");
+
+        // Pairs without rule are returned once normal form is reached
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(x, r) ~ S(S(O)), x ~ O, FOO ~ BAR()".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        let (dot, stuck) = frames(&mut vm, &tm, Some(1)).unwrap();
+        assert_eq!(dot.len(), 2);
+        assert!(stuck.is_empty());
+        let (dot, stuck) = frames(&mut vm, &tm, None).unwrap();
+        assert_eq!(dot.len(), 3);
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].atypes, (*tm.get("FOO").unwrap(), *tm.get("BAR").unwrap()));
+        assert_eq!(readback_interface(&vm, &tm, "r").unwrap().to_string(), "S(S(O))");
+    }
+
+    #[test]
+    pub fn test_json() {
+        use crate::interaction::json::{vm_from_json, vm_to_json};
+
+        let s = "
+        ADD(x, r) # S(y) = ADD(x, q) ~ y, r ~ S(q)
+        ADD(x, r) # O() = x ~ r
+        ADD(S(O), r) ~ S(S(O)),
+        +(1, n) ~ 2".to_string();
+        let pg = Semantic::Program::fromAst(ast(&s).unwrap()).unwrap();
+        let (mut vm, tm) = compileProgram(pg).unwrap();
+        vm.reduce_with_limit(1).unwrap();
+
+        // Net is edited between interactions and reduced further after loading it back
+        let text = vm_to_json(&vm, &tm);
+        let mut document: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let rule = document["rules"].as_array().unwrap().iter().find(|rule| rule["left"] == "ADD" && rule["right"] == "S").unwrap();
+        assert_eq!((&rule["code"][0], &rule["code"][1]), (&"POP 0".into(), &"POP 1".into()));
+        // Partial operator holds the 2 and waits for the 1
+        let agents = document["net"]["agents"].as_array_mut().unwrap();
+        let partial = agents.iter_mut().find(|agent| agent["type"] == "+_").unwrap();
+        assert_eq!(partial["value"], 2);
+        partial["value"] = 40.into();
+        let (mut loaded, types) = vm_from_json(&document.to_string()).unwrap();
+        assert_eq!(types, tm);
+        assert!(loaded.reduce().unwrap().is_empty());
+        assert_eq!(readback_interface(&loaded, &tm, "r").unwrap().to_string(), "S(S(S(O)))");
+        assert_eq!(readback_interface(&loaded, &tm, "n").unwrap().to_string(), "41");
     }
-}
\ No newline at end of file
+}